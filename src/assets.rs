@@ -0,0 +1,91 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use failure::{err_msg, Error};
+use std::io::Read;
+use url::Url;
+
+/// An image or other file referenced by a page, loaded into memory.
+pub struct Asset {
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
+impl Asset {
+    /// Loads the asset from a data url, or downloads it.
+    /// Urls without a scheme are relative to the site, as with uploaded assets.
+    pub fn load(url: &str, site: &str) -> Result<Asset, Error> {
+        if url.starts_with("data:") {
+            return Asset::from_data_url(url);
+        }
+        let parsed_url = Url::parse(site)?.join(url)?;
+        eprintln!("Loading: {}", parsed_url);
+        let mut response = reqwest::get(parsed_url.as_str())?;
+        if !response.status().is_success() {
+            return Err(err_msg(format!(
+                "Unable to retrieve {}: {}",
+                parsed_url,
+                response.status()
+            )));
+        }
+        let mime = response
+            .headers()
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.split(';').next().unwrap().trim().to_owned())
+            .unwrap_or_else(|| mime_from_path(parsed_url.path()).to_owned());
+        let mut data = Vec::new();
+        response.read_to_end(&mut data)?;
+        Ok(Asset { mime, data })
+    }
+
+    /// Decodes `data:image/jpeg;base64,...`, the form the wiki stores image items in.
+    pub fn from_data_url(url: &str) -> Result<Asset, Error> {
+        let mut parts = url["data:".len()..].splitn(2, ',');
+        let header = parts.next().unwrap();
+        let payload = parts
+            .next()
+            .ok_or_else(|| err_msg("Data url has no payload."))?;
+        let mime = header.split(';').next().unwrap_or("").to_owned();
+        let data = if header.ends_with(";base64") {
+            STANDARD.decode(payload.trim())?
+        } else {
+            payload.as_bytes().to_vec()
+        };
+        Ok(Asset { mime, data })
+    }
+
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime, STANDARD.encode(&self.data))
+    }
+
+    /// The file extension for the asset's type.
+    pub fn extension(&self) -> &str {
+        match self.mime.as_str() {
+            "image/jpeg" => "jpg",
+            "image/png" => "png",
+            "image/gif" => "gif",
+            "image/svg+xml" => "svg",
+            "image/webp" => "webp",
+            "application/pdf" => "pdf",
+            "text/css" => "css",
+            _ => "bin",
+        }
+    }
+}
+
+/// Guesses the type of a file from its extension.
+pub fn mime_from_path(path: &str) -> &'static str {
+    let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "pdf" => "application/pdf",
+        "css" => "text/css",
+        "html" => "text/html",
+        "json" => "application/json",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,577 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use clap::{App, Arg, ArgMatches};
+use failure::{err_msg, Error};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{exit, Command, Stdio};
+use std::{thread, time};
+use wiki_rust::config::{load_neighborhood, DEFAULT_ROSTER_SITE, DEFAULT_ROSTER_SLUG};
+use wiki_rust::filter::ChangeFilter;
+use wiki_rust::format::{ChangeRecord, Format, FORMATS};
+use wiki_rust::journal::JournalSummary;
+use wiki_rust::state::SeenState;
+mod tui;
+
+use wiki_rust::{parse_time_spec, relative_time, Entry, Item, Neighborhood, Page, RecentChange};
+
+fn print_detail(page: &Page, since: Option<NaiveDateTime>, until: Option<NaiveDateTime>) {
+    let summary = JournalSummary::from_page(page, since, until);
+    println!("\t\t{}", summary);
+    for diff in &summary.diffs {
+        println!("\t\t  {}", diff);
+    }
+}
+
+/// Runs the user's command through the shell with the change(s) as JSON on stdin.
+/// A single change is also described by WIKI_* environment variables.
+fn run_hook(command: &str, record: Option<&ChangeRecord>, json: &str) -> Result<(), Error> {
+    let mut hook = if cfg!(target_os = "windows") {
+        let mut hook = Command::new("cmd");
+        hook.args(&["/C", command]);
+        hook
+    } else {
+        let mut hook = Command::new("sh");
+        hook.arg("-c").arg(command);
+        hook
+    };
+    if let Some(record) = record {
+        hook.env("WIKI_SITE", &record.site)
+            .env("WIKI_SLUG", &record.slug)
+            .env("WIKI_TITLE", &record.title)
+            .env("WIKI_DATE", &record.date)
+            .env("WIKI_URL", &record.url);
+    }
+    let mut child = hook.stdin(Stdio::piped()).spawn()?;
+    // The hook may exit without reading its input.
+    let _ = child.stdin.take().unwrap().write_all(json.as_bytes());
+    let status = child.wait()?;
+    if !status.success() {
+        eprintln!("Hook exited with {}: {}", status, command);
+    }
+    Ok(())
+}
+
+fn page_key(change: &RecentChange) -> (String, String) {
+    (change.site.name.clone(), change.entry.slug.clone())
+}
+
+fn print_change(change: &RecentChange, group: &str) {
+    match group {
+        "site" => println!("\t{}", change.entry.title),
+        "day" => println!(
+            "\t{}\t{}\t{}",
+            change.site.name,
+            change.entry.title,
+            relative_time(&change.entry.date)
+        ),
+        _ => println!(
+            "{}\t{}\t{}",
+            change.entry.date.format("%Y-%m-%d %H:%M"),
+            change.site.name,
+            change.entry.title
+        ),
+    }
+}
+
+/// A fedwiki page with a reference to each changed page.
+fn digest_page(title: &str, changes: &[RecentChange], since: Option<NaiveDateTime>) -> Page {
+    let mut sites: Vec<&str> = changes.iter().map(|c| c.site.name.as_str()).collect();
+    sites.sort();
+    sites.dedup();
+    let mut header = format!("{} changes in {} sites", changes.len(), sites.len());
+    if let Some(since) = since {
+        header.push_str(&format!(" since {}", since.format("%B %e, %Y")));
+    }
+    header.push('.');
+    let mut story = vec![Item::new("paragraph", &header)];
+    for change in changes {
+        let mut item = Item::new("reference", &change.entry.synopsis);
+        item.site = Some(change.site.host().to_owned());
+        item.slug = Some(change.entry.slug.clone());
+        item.title = Some(change.entry.title.clone());
+        story.push(item);
+    }
+    Page::create(title, story)
+}
+
+/// The changes chosen by the command line options, in display order.
+struct Selection<'a> {
+    changes: Vec<RecentChange<'a>>,
+    /// Changes that would have been shown but for --limit.
+    left_out: Vec<RecentChange<'a>>,
+    pages: HashMap<(String, String), Page>,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    group: &'a str,
+}
+
+fn select_changes<'a>(
+    matches: &'a ArgMatches,
+    neighborhood: &'a Neighborhood,
+    state: Option<&SeenState>,
+) -> Result<Selection<'a>, Error> {
+    let mut since = match matches.value_of("since") {
+        Some(since) => Some(parse_time_spec(since, false)?),
+        None => None,
+    };
+    if let Some(days) = matches.value_of("days") {
+        let days = days
+            .parse::<i64>()
+            .map_err(|_| err_msg(format!("Invalid number of days: {}", days)))?;
+        since = Some(Utc::now().naive_utc() - Duration::days(days));
+    }
+    let until = match matches.value_of("until") {
+        Some(until) => Some(parse_time_spec(until, true)?),
+        None => None,
+    };
+    let limit = match matches.value_of("limit") {
+        Some(limit) => Some(
+            limit
+                .parse::<usize>()
+                .map_err(|_| err_msg(format!("Invalid limit: {}", limit)))?,
+        ),
+        None => None,
+    };
+    let selected = |entry: &Entry| {
+        if since.map(|since| since > entry.date).unwrap_or(false)
+            || until.map(|until| until < entry.date).unwrap_or(false)
+        {
+            return false;
+        }
+        true
+    };
+    let mut filter = ChangeFilter::new(since, until);
+    if let Some(pattern) = matches.value_of("match") {
+        filter.pattern = Some(Regex::new(pattern)?);
+    }
+    filter.text = matches.value_of("text").map(|t| t.to_owned());
+    if let Some(actions) = matches.values_of("action") {
+        filter.actions = actions.map(|a| a.to_owned()).collect();
+    }
+    if let Some(item_types) = matches.values_of("item-type") {
+        filter.item_types = item_types.map(|t| t.to_owned()).collect();
+    }
+    // Pages are loaded at most once, for filtering or for --detail.
+    let mut pages: HashMap<(String, String), Page> = HashMap::new();
+    let mut per_site: HashMap<&str, usize> = HashMap::new();
+    let mut changes: Vec<RecentChange> = Vec::new();
+    let mut left_out: Vec<RecentChange> = Vec::new();
+    for change in neighborhood.changes() {
+        if !selected(change.entry) || !filter.matches_entry(change.entry) {
+            continue;
+        }
+        if state
+            .map(|state| !state.is_unseen(&change))
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        if filter.needs_page() {
+            let page = match change.site.page(&change.entry.slug) {
+                Ok(page) => page,
+                Err(err) => {
+                    eprintln!("Unable to load {}: {}", change.entry.slug, err);
+                    continue;
+                }
+            };
+            if !filter.matches_page(&page) {
+                continue;
+            }
+            pages.insert(page_key(&change), page);
+        }
+        // Checked last, so that the changes left out would otherwise have been shown.
+        if limit
+            .map(|limit| per_site.get(change.site.name.as_str()) >= Some(&limit))
+            .unwrap_or(false)
+        {
+            left_out.push(change);
+            continue;
+        }
+        *per_site.entry(&change.site.name).or_insert(0) += 1;
+        changes.push(change);
+    }
+    match matches.value_of("sort").unwrap() {
+        "title" => changes.sort_by_key(|c| c.entry.title.to_lowercase()),
+        "site" => changes.sort_by(|a, b| a.site.name.cmp(&b.site.name)),
+        _ => {}
+    }
+    let group = if matches.is_present("merge") {
+        "day"
+    } else {
+        matches.value_of("group").unwrap()
+    };
+    if group == "day" {
+        changes.sort_by(|a, b| b.entry.date.date().cmp(&a.entry.date.date()));
+    }
+    Ok(Selection {
+        changes,
+        left_out,
+        pages,
+        since,
+        until,
+        group,
+    })
+}
+
+/// Reports the selected changes. Returns them, and those left out by --limit,
+/// so that they can be marked as seen.
+fn report<'a>(
+    matches: &'a ArgMatches,
+    neighborhood: &'a Neighborhood,
+    state: Option<&SeenState>,
+) -> Result<(Vec<RecentChange<'a>>, Vec<RecentChange<'a>>), Error> {
+    let Selection {
+        changes,
+        left_out,
+        pages,
+        since,
+        until,
+        group,
+    } = select_changes(matches, neighborhood, state)?;
+    // Hooks only see unseen changes, as --exec cannot be combined with --all.
+    if let Some(command) = matches.value_of("exec") {
+        let records: Vec<ChangeRecord> = changes.iter().map(ChangeRecord::from).collect();
+        if matches.is_present("batch") {
+            if records.len() > 0 {
+                run_hook(command, None, &serde_json::to_string(&records)?)?;
+            }
+        } else {
+            for record in &records {
+                run_hook(command, Some(record), &serde_json::to_string(record)?)?;
+            }
+        }
+    }
+    if let Some(path) = matches.value_of("emit-page") {
+        let page = digest_page(matches.value_of("page-title").unwrap(), &changes, since);
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, &page)?;
+        eprintln!("Wrote {} changes to {}", changes.len(), path);
+        return Ok((changes, left_out));
+    }
+    let format: Format = matches.value_of("format").unwrap().parse()?;
+    if format != Format::Text {
+        let records: Vec<ChangeRecord> = changes.iter().map(ChangeRecord::from).collect();
+        let stdout = io::stdout();
+        format.write(&mut stdout.lock(), &records)?;
+        return Ok((changes, left_out));
+    }
+    let detail = matches.is_present("detail");
+    let print = |change: &RecentChange| {
+        print_change(change, group);
+        if detail {
+            match pages.get(&page_key(change)) {
+                Some(page) => print_detail(page, since, until),
+                None => match change.site.page(&change.entry.slug) {
+                    Ok(page) => print_detail(&page, since, until),
+                    Err(err) => println!("\t\tUnable to load page: {}", err),
+                },
+            }
+        }
+    };
+    match group {
+        "site" => {
+            for site in &neighborhood.sites {
+                let site_changes: Vec<&RecentChange> = changes
+                    .iter()
+                    .filter(|c| std::ptr::eq(c.site, site))
+                    .collect();
+                if state.is_some() && site_changes.len() == 0 {
+                    continue;
+                }
+                println!("{}", site.name);
+                for change in site_changes {
+                    print(change);
+                }
+            }
+        }
+        "day" => {
+            let mut day = None;
+            for change in &changes {
+                let date = change.entry.date.date();
+                if day != Some(date) {
+                    println!("{}", date.format("%A, %B %e, %Y"));
+                    day = Some(date);
+                }
+                print(change);
+            }
+        }
+        _ => {
+            for change in &changes {
+                print(change);
+            }
+        }
+    }
+    Ok((changes, left_out))
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let watch = matches.is_present("watch");
+    let unseen = !matches.is_present("all");
+    let browse = matches.is_present("tui");
+    if !unseen && !browse {
+        report(matches, &load_neighborhood(matches)?, None)?;
+        return Ok(());
+    }
+    let state_path = matches
+        .value_of("state")
+        .map(PathBuf::from)
+        .unwrap_or_else(SeenState::default_path);
+    let mut state = SeenState::load(&state_path)?;
+    if browse {
+        // Browsing keeps read marks in the state file, but leaves what has been seen
+        // to reports, so that changes are not lost by merely being listed.
+        let neighborhood = load_neighborhood(matches)?;
+        let selection = select_changes(matches, &neighborhood, Some(&state).filter(|_| unseen))?;
+        tui::browse(&selection.changes, &mut state)?;
+        state.save(&state_path)?;
+        return Ok(());
+    }
+    let interval = matches.value_of("interval").unwrap();
+    let interval = interval
+        .parse::<u64>()
+        .map_err(|_| err_msg(format!("Invalid interval: {}", interval)))?;
+    loop {
+        match load_neighborhood(matches) {
+            Ok(neighborhood) => {
+                let (shown, left_out) = report(matches, &neighborhood, Some(&state))?;
+                state.mark_seen(&shown, &left_out);
+                state.save(&state_path)?;
+            }
+            Err(err) if watch => eprintln!("{}", err),
+            Err(err) => return Err(err),
+        }
+        if !watch {
+            return Ok(());
+        }
+        thread::sleep(time::Duration::from_secs(interval));
+    }
+}
+
+fn main() {
+    let matches = App::new("wiki-changes")
+        .about("Get recent changes for fed wiki sites.")
+        .arg(
+            Arg::with_name("pod")
+                .long("pod")
+                .short("p")
+                .help("Look for changes in the sites listed on the roster page."),
+        )
+        .arg(
+            Arg::with_name("roster-site")
+                .long("roster-site")
+                .takes_value(true)
+                .default_value(DEFAULT_ROSTER_SITE)
+                .help("The site holding the roster page used by --pod."),
+        )
+        .arg(
+            Arg::with_name("roster-slug")
+                .long("roster-slug")
+                .takes_value(true)
+                .default_value(DEFAULT_ROSTER_SLUG)
+                .help("The slug of the roster page used by --pod."),
+        )
+        .arg(
+            Arg::with_name("neighborhood")
+                .long("neighborhood")
+                .short("n")
+                .takes_value(true)
+                .help("Look for changes in a neighborhood named in the config file."),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .help("The config file defining neighborhoods."),
+        )
+        .arg(
+            Arg::with_name("site")
+                .long("site")
+                .short("s")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Look for changes in the specified site. May be repeated. Filters the roster with --pod or --neighborhood."),
+        )
+        .arg(
+            Arg::with_name("sites-file")
+                .long("sites-file")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("FILE")
+                .help("Look for changes in the sites listed one per line in a file. # starts a comment."),
+        )
+        .arg(
+            Arg::with_name("opml")
+                .long("opml")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("FILE")
+                .help("Look for changes in the sites of an OPML outline."),
+        )
+        .arg(
+            Arg::with_name("days")
+                .long("days")
+                .short("d")
+                .takes_value(true)
+                .conflicts_with("since")
+                .help("Only retrieve changes within the number of days specified."),
+        )
+        .arg(
+            Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .help("Only retrieve changes after a date (2020-01-31) or relative time (2w, 3d)."),
+        )
+        .arg(
+            Arg::with_name("until")
+                .long("until")
+                .takes_value(true)
+                .help("Only retrieve changes up to a date (inclusive) or relative time."),
+        )
+        .arg(
+            Arg::with_name("sort")
+                .long("sort")
+                .takes_value(true)
+                .possible_values(&["date", "title", "site"])
+                .default_value("date")
+                .help("Order of the changes."),
+        )
+        .arg(
+            Arg::with_name("group")
+                .long("group")
+                .short("g")
+                .takes_value(true)
+                .possible_values(&["site", "day", "none"])
+                .default_value("site")
+                .help("How changes are grouped in text output."),
+        )
+        .arg(
+            Arg::with_name("limit")
+                .long("limit")
+                .short("l")
+                .takes_value(true)
+                .help("The maximum number of changes to show per site."),
+        )
+        .arg(
+            Arg::with_name("merge")
+                .long("merge")
+                .short("m")
+                .help("Merge all sites into one timeline grouped by day. Same as --group day."),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .short("f")
+                .takes_value(true)
+                .possible_values(FORMATS)
+                .default_value("text")
+                .help("Output format."),
+        )
+        .arg(
+            Arg::with_name("match")
+                .long("match")
+                .takes_value(true)
+                .value_name("REGEX")
+                .help("Only show changes whose title or synopsis matches the regular expression."),
+        )
+        .arg(
+            Arg::with_name("text")
+                .long("text")
+                .takes_value(true)
+                .help("Only show pages whose story contains the text. Loads each page."),
+        )
+        .arg(
+            Arg::with_name("action")
+                .long("action")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only show pages with a journal action of this type, e.g. fork or create."),
+        )
+        .arg(
+            Arg::with_name("item-type")
+                .long("item-type")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only show pages that gained an item of this type, e.g. video or code."),
+        )
+        .arg(
+            Arg::with_name("detail")
+                .long("detail")
+                .help("Summarize the journal actions of each changed page within the time window."),
+        )
+        .arg(
+            Arg::with_name("emit-page")
+                .long("emit-page")
+                .takes_value(true)
+                .value_name("FILE")
+                .help(
+                    "Write the changes as a fedwiki page JSON file with a reference to each page.",
+                ),
+        )
+        .arg(
+            Arg::with_name("page-title")
+                .long("page-title")
+                .takes_value(true)
+                .default_value("Recent Changes")
+                .help("The title of the page written by --emit-page."),
+        )
+        .arg(
+            Arg::with_name("exec")
+                .long("exec")
+                .short("x")
+                .takes_value(true)
+                .value_name("COMMAND")
+                .conflicts_with("all")
+                .help("Run a command for each unseen change with WIKI_SITE, WIKI_SLUG, WIKI_TITLE, WIKI_DATE and WIKI_URL set and a JSON record on stdin."),
+        )
+        .arg(
+            Arg::with_name("batch")
+                .long("batch")
+                .requires("exec")
+                .help("Run the --exec command once with a JSON array of the unseen changes on stdin."),
+        )
+        .arg(
+            Arg::with_name("tui")
+                .long("tui")
+                .short("t")
+                .help("Browse the changes and their pages in an interactive terminal UI."),
+        )
+        .arg(
+            Arg::with_name("all")
+                .long("all")
+                .short("a")
+                .conflicts_with("watch")
+                .help("Show every change, not only those newer than the last run, and leave them unseen."),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .short("w")
+                .help("Poll for changes on an interval, showing those since the last poll."),
+        )
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .takes_value(true)
+                .default_value("300")
+                .help("Seconds to wait between polls in watch mode."),
+        )
+        .arg(
+            Arg::with_name("state")
+                .long("state")
+                .takes_value(true)
+                .help("The file in which last seen dates are kept."),
+        )
+        .get_matches();
+    if let Err(err) = run(&matches) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
@@ -0,0 +1,439 @@
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::{cursor, execute, queue, terminal};
+use failure::Error;
+use std::io::{self, Write};
+use wiki_rust::links::{parse_links, slug, Markup};
+use wiki_rust::state::SeenState;
+use wiki_rust::{site_url, Page, RecentChange};
+
+const MIN_PANE_WIDTH: usize = 40;
+
+/// Shows the changes in a list. Enter opens the page in a lineup of panes,
+/// where links can be followed to the right, like in the wiki client.
+pub fn browse(changes: &[RecentChange], state: &mut SeenState) -> Result<(), Error> {
+    terminal::enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+    let mut browser = Browser {
+        changes,
+        state,
+        selected: 0,
+        top: 0,
+        panes: Vec::new(),
+        focus: 0,
+        height: 0,
+        status: String::new(),
+    };
+    let result = browser.run(&mut stdout);
+    execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    result
+}
+
+struct Target {
+    /// The site's url, or its host as references name it.
+    site: String,
+    slug: String,
+}
+
+#[derive(Clone)]
+struct Word {
+    text: String,
+    link: Option<usize>,
+    bold: bool,
+}
+
+/// A page in the lineup. Its story is kept as paragraphs of words
+/// so that it can be wrapped to whatever width the pane gets.
+struct Pane {
+    /// The url of the page's site, which its links are relative to.
+    site: String,
+    paragraphs: Vec<Vec<Word>>,
+    links: Vec<Target>,
+    link: Option<usize>,
+    scroll: usize,
+    width: usize,
+}
+
+impl Pane {
+    fn new(site: &str, page: &Page) -> Pane {
+        let mut pane = Pane {
+            site: site.to_owned(),
+            paragraphs: Vec::new(),
+            links: Vec::new(),
+            link: None,
+            scroll: 0,
+            width: MIN_PANE_WIDTH,
+        };
+        let title = page
+            .title
+            .split_whitespace()
+            .map(|w| Word {
+                text: w.to_owned(),
+                link: None,
+                bold: true,
+            })
+            .collect();
+        pane.paragraphs.push(title);
+        for item in &page.story {
+            pane.paragraphs.push(Vec::new());
+            let text = item.text();
+            match item.r#type.as_str() {
+                "reference" => {
+                    let site = item.site.clone().unwrap_or_else(|| site.to_owned());
+                    let title = item.title.as_ref().or(item.slug.as_ref());
+                    let mut words = vec![plain("»")];
+                    if let (Some(title), Some(slug)) = (title, &item.slug) {
+                        pane.links.push(Target {
+                            site: site.clone(),
+                            slug: slug.clone(),
+                        });
+                        let link = Some(pane.links.len() - 1);
+                        words.extend(title.split_whitespace().map(|w| Word {
+                            text: w.to_owned(),
+                            link,
+                            bold: false,
+                        }));
+                    }
+                    words.push(plain(&format!("({})", site)));
+                    pane.paragraphs.push(words);
+                    pane.add_text(text);
+                }
+                "paragraph" | "markdown" => pane.add_text(text),
+                other => {
+                    pane.paragraphs.push(vec![plain(&format!("[{}]", other))]);
+                    pane.add_text(text);
+                }
+            }
+        }
+        pane
+    }
+
+    fn add_text(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut words = Vec::new();
+            for markup in parse_links(line) {
+                let (text, link) = match markup {
+                    Markup::Text(text) => (text, None),
+                    Markup::Internal(title) => {
+                        self.links.push(Target {
+                            site: self.site.clone(),
+                            slug: slug(title),
+                        });
+                        (title, Some(self.links.len() - 1))
+                    }
+                    Markup::External { label, .. } => (label, None),
+                };
+                words.extend(text.split_whitespace().map(|w| Word {
+                    text: w.to_owned(),
+                    link,
+                    bold: false,
+                }));
+            }
+            self.paragraphs.push(words);
+        }
+    }
+
+    fn lines(&self) -> Vec<Vec<Word>> {
+        let mut lines = Vec::new();
+        for paragraph in &self.paragraphs {
+            let mut line: Vec<Word> = Vec::new();
+            let mut length = 0;
+            for word in paragraph {
+                let mut word = word.clone();
+                // Hard split words wider than the pane.
+                while word.text.chars().count() > self.width {
+                    let head: String = word.text.chars().take(self.width).collect();
+                    let tail: String = word.text.chars().skip(self.width).collect();
+                    if line.len() > 0 {
+                        lines.push(line);
+                        line = Vec::new();
+                    }
+                    lines.push(vec![Word {
+                        text: head,
+                        ..word.clone()
+                    }]);
+                    word.text = tail;
+                    length = 0;
+                }
+                let word_length = word.text.chars().count();
+                if line.len() > 0 && length + 1 + word_length > self.width {
+                    lines.push(line);
+                    line = Vec::new();
+                    length = 0;
+                }
+                length += word_length + if line.len() > 0 { 1 } else { 0 };
+                line.push(word);
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Scrolls so that the selected link is within the visible lines.
+    fn reveal_link(&mut self, height: usize) {
+        let link = match self.link {
+            Some(link) => link,
+            None => return,
+        };
+        let lines = self.lines();
+        if let Some(index) = lines
+            .iter()
+            .position(|line| line.iter().any(|w| w.link == Some(link)))
+        {
+            if index < self.scroll {
+                self.scroll = index;
+            } else if index >= self.scroll + height {
+                self.scroll = index + 1 - height;
+            }
+        }
+    }
+}
+
+fn plain(text: &str) -> Word {
+    Word {
+        text: text.to_owned(),
+        link: None,
+        bold: false,
+    }
+}
+
+fn fit(text: &str, width: usize) -> String {
+    let mut text: String = text.chars().take(width).collect();
+    let length = text.chars().count();
+    text.extend(std::iter::repeat(' ').take(width - length));
+    text
+}
+
+struct Browser<'a, 'b> {
+    changes: &'b [RecentChange<'a>],
+    state: &'b mut SeenState,
+    selected: usize,
+    top: usize,
+    panes: Vec<Pane>,
+    focus: usize,
+    height: usize,
+    status: String,
+}
+
+impl<'a, 'b> Browser<'a, 'b> {
+    fn run(&mut self, out: &mut io::Stdout) -> Result<(), Error> {
+        loop {
+            self.draw(out)?;
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            self.status.clear();
+            let quit = if self.panes.len() == 0 {
+                self.list_key(key.code)
+            } else {
+                self.lineup_key(key.code)
+            };
+            if quit {
+                return Ok(());
+            }
+        }
+    }
+
+    fn list_key(&mut self, code: KeyCode) -> bool {
+        let last = self.changes.len().saturating_sub(1);
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => self.selected = (self.selected + 1).min(last),
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(self.height),
+            KeyCode::PageDown => self.selected = (self.selected + self.height).min(last),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = last,
+            KeyCode::Char('r') => {
+                if let Some(change) = self.changes.get(self.selected) {
+                    self.state.toggle_read(change);
+                }
+            }
+            KeyCode::Enter => {
+                if let Some(change) = self.changes.get(self.selected) {
+                    self.state.read.insert(SeenState::read_key(change));
+                    self.open(&change.site.url.clone(), &change.entry.slug.clone());
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+
+    fn lineup_key(&mut self, code: KeyCode) -> bool {
+        let height = self.height;
+        let pane = &mut self.panes[self.focus];
+        match code {
+            KeyCode::Char('q') => return true,
+            KeyCode::Esc | KeyCode::Backspace => {
+                self.panes.pop();
+                self.focus = self.focus.min(self.panes.len().saturating_sub(1));
+            }
+            KeyCode::Left | KeyCode::Char('h') => self.focus = self.focus.saturating_sub(1),
+            KeyCode::Right | KeyCode::Char('l') => {
+                self.focus = (self.focus + 1).min(self.panes.len() - 1)
+            }
+            KeyCode::Up | KeyCode::Char('k') => pane.scroll = pane.scroll.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => pane.scroll += 1,
+            KeyCode::PageUp => pane.scroll = pane.scroll.saturating_sub(height),
+            KeyCode::PageDown => pane.scroll += height,
+            KeyCode::Tab | KeyCode::BackTab if pane.links.len() > 0 => {
+                let count = pane.links.len();
+                pane.link = Some(match (pane.link, code) {
+                    (None, KeyCode::Tab) => 0,
+                    (None, _) => count - 1,
+                    (Some(link), KeyCode::Tab) => (link + 1) % count,
+                    (Some(link), _) => (link + count - 1) % count,
+                });
+                pane.reveal_link(height);
+            }
+            KeyCode::Enter => {
+                if let Some(link) = pane.link {
+                    let target = &pane.links[link];
+                    let (site, slug) = (target.site.clone(), target.slug.clone());
+                    self.panes.truncate(self.focus + 1);
+                    self.open(&site, &slug);
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+
+    fn open(&mut self, site: &str, slug: &str) {
+        match Page::from_site_slug(&site_url(site), slug) {
+            Ok(page) => {
+                self.panes.push(Pane::new(site, &page));
+                self.focus = self.panes.len() - 1;
+            }
+            Err(err) => self.status = format!("Unable to load {}/{}: {}", site, slug, err),
+        }
+    }
+
+    fn draw(&mut self, out: &mut io::Stdout) -> Result<(), Error> {
+        let (columns, rows) = terminal::size()?;
+        let (columns, rows) = (columns as usize, rows as usize);
+        self.height = rows.saturating_sub(2).max(1);
+        queue!(out, terminal::Clear(terminal::ClearType::All))?;
+        if self.panes.len() == 0 {
+            self.draw_list(out, columns)?;
+        } else {
+            self.draw_lineup(out, columns)?;
+        }
+        let help = if self.status.len() > 0 {
+            self.status.clone()
+        } else if self.panes.len() == 0 {
+            "enter open  r read/unread  j/k move  q quit".to_owned()
+        } else {
+            "tab next link  enter follow  h/l pane  j/k scroll  esc close  q quit".to_owned()
+        };
+        queue!(
+            out,
+            cursor::MoveTo(0, (rows - 1) as u16),
+            SetAttribute(Attribute::Reverse),
+            Print(fit(&help, columns)),
+            SetAttribute(Attribute::Reset)
+        )?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn draw_list(&mut self, out: &mut io::Stdout, columns: usize) -> Result<(), Error> {
+        let unread = self
+            .changes
+            .iter()
+            .filter(|c| !self.state.is_read(c))
+            .count();
+        queue!(
+            out,
+            cursor::MoveTo(0, 0),
+            SetAttribute(Attribute::Bold),
+            Print(fit(
+                &format!("Recent changes: {} ({} unread)", self.changes.len(), unread),
+                columns
+            )),
+            SetAttribute(Attribute::Reset)
+        )?;
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + self.height {
+            self.top = self.selected + 1 - self.height;
+        }
+        for (row, (index, change)) in self
+            .changes
+            .iter()
+            .enumerate()
+            .skip(self.top)
+            .take(self.height)
+            .enumerate()
+        {
+            let line = format!(
+                "{} {}  {}  {}",
+                if self.state.is_read(change) { " " } else { "*" },
+                change.entry.date.format("%Y-%m-%d %H:%M"),
+                change.entry.title,
+                change.site.name
+            );
+            queue!(out, cursor::MoveTo(0, (row + 1) as u16))?;
+            if index == self.selected {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(
+                out,
+                Print(fit(&line, columns)),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Shows as many panes as fit, keeping the focused pane visible.
+    fn draw_lineup(&mut self, out: &mut io::Stdout, columns: usize) -> Result<(), Error> {
+        let visible = (columns / MIN_PANE_WIDTH).max(1).min(self.panes.len());
+        let first = (self.focus + 1).saturating_sub(visible);
+        let first = first.min(self.panes.len() - visible);
+        let width = columns / visible;
+        for (column, index) in (first..first + visible).enumerate() {
+            let x = (column * width) as u16;
+            let focused = index == self.focus;
+            let pane = &mut self.panes[index];
+            pane.width = width.saturating_sub(2).max(1);
+            let lines = pane.lines();
+            pane.scroll = pane.scroll.min(lines.len().saturating_sub(1));
+            queue!(out, cursor::MoveTo(x, 0))?;
+            if focused {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(
+                out,
+                Print(fit(
+                    pane.site.splitn(2, "://").last().unwrap(),
+                    width.saturating_sub(1)
+                )),
+                SetAttribute(Attribute::Reset)
+            )?;
+            for (row, line) in lines.iter().skip(pane.scroll).take(self.height).enumerate() {
+                queue!(out, cursor::MoveTo(x + 1, (row + 1) as u16))?;
+                for (position, word) in line.iter().enumerate() {
+                    if position > 0 {
+                        queue!(out, Print(" "))?;
+                    }
+                    if word.bold {
+                        queue!(out, SetAttribute(Attribute::Bold))?;
+                    }
+                    if word.link.is_some() {
+                        queue!(out, SetAttribute(Attribute::Underlined))?;
+                    }
+                    if focused && word.link.is_some() && word.link == pane.link {
+                        queue!(out, SetAttribute(Attribute::Reverse))?;
+                    }
+                    queue!(out, Print(&word.text), SetAttribute(Attribute::Reset))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
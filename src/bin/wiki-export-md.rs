@@ -0,0 +1,76 @@
+use clap::{App, Arg, ArgMatches};
+use failure::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+use wiki_rust::markdown::{MarkdownExporter, WikiLinks};
+use wiki_rust::{site_url, Page, Sitemap};
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let site = site_url(matches.value_of("site").unwrap());
+    let sitemap = Sitemap::from_url(&site)?;
+    let out = PathBuf::from(matches.value_of("out").unwrap_or(&sitemap.name));
+    let links = match matches.value_of("links").unwrap() {
+        "relative" => WikiLinks::Relative,
+        _ => WikiLinks::Wiki,
+    };
+    let exporter = MarkdownExporter::new(&site, links);
+    fs::create_dir_all(&out)?;
+    let mut exported = 0;
+    for entry in &sitemap.entries {
+        let export = match Page::from_site_slug(&site, &entry.slug)
+            .and_then(|page| exporter.page(entry, &page))
+        {
+            Ok(export) => export,
+            Err(err) => {
+                eprintln!("Skipping {}: {}", entry.slug, err);
+                continue;
+            }
+        };
+        for (path, asset) in export.assets {
+            let path = out.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, asset.data)?;
+        }
+        fs::write(out.join(format!("{}.md", entry.slug)), export.markdown)?;
+        exported += 1;
+    }
+    eprintln!("Exported {} pages to {}", exported, out.display());
+    Ok(())
+}
+
+fn main() {
+    let matches = App::new("wiki-export-md")
+        .about("Exports the pages of a federated wiki site as Markdown files.")
+        .arg(
+            Arg::with_name("site")
+                .long("site")
+                .short("s")
+                .required(true)
+                .takes_value(true)
+                .help("The site to export."),
+        )
+        .arg(
+            Arg::with_name("out")
+                .long("out")
+                .short("o")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("The directory to export to. Defaults to the site's name."),
+        )
+        .arg(
+            Arg::with_name("links")
+                .long("links")
+                .takes_value(true)
+                .possible_values(&["wiki", "relative"])
+                .default_value("wiki")
+                .help("Keep links to pages as [[wiki links]] or write them as relative links to .md files."),
+        )
+        .get_matches();
+    if let Err(err) = run(&matches) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
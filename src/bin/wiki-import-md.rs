@@ -0,0 +1,100 @@
+use chrono::{DateTime, Utc};
+use clap::{App, Arg, ArgGroup, ArgMatches};
+use failure::{err_msg, Error};
+use glob::glob;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+use wiki_rust::links::slug;
+use wiki_rust::markdown::MarkdownImporter;
+use wiki_rust::Page;
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let notes = Path::new(matches.value_of("notes").unwrap());
+    if !notes.is_dir() {
+        return Err(err_msg(format!("Not a folder: {}", notes.display())));
+    }
+    let mut importer = MarkdownImporter::new(notes);
+    // Every title is needed before any note is converted, for the links between them.
+    let mut notes_read = Vec::new();
+    let pattern = notes.join("**").join("*.md");
+    for path in glob(pattern.to_str().unwrap())? {
+        let path = path?;
+        let markdown = fs::read_to_string(&path)?;
+        importer.add_note(&path, &markdown);
+        notes_read.push((path, markdown));
+    }
+    let mut pages: BTreeMap<String, Page> = BTreeMap::new();
+    for (path, markdown) in notes_read {
+        let modified: DateTime<Utc> = fs::metadata(&path)?.modified()?.into();
+        let page = importer.page(&path, &markdown, modified.naive_utc());
+        let page_slug = slug(&page.title);
+        if page_slug.len() == 0 {
+            eprintln!(
+                "Skipping {}: its title has no letters or digits",
+                path.display()
+            );
+            continue;
+        }
+        if pages.contains_key(&page_slug) {
+            eprintln!(
+                "Skipping {}: another note is already the page {}",
+                path.display(),
+                page_slug
+            );
+            continue;
+        }
+        pages.insert(page_slug, page);
+    }
+    if let Some(dir) = matches.value_of("pages") {
+        let dir = Path::new(dir);
+        fs::create_dir_all(dir)?;
+        for (page_slug, page) in &pages {
+            fs::write(dir.join(page_slug), serde_json::to_string_pretty(page)?)?;
+        }
+        eprintln!("Wrote {} pages to {}", pages.len(), dir.display());
+    } else if let Some(file) = matches.value_of("export") {
+        fs::write(file, serde_json::to_string_pretty(&pages)?)?;
+        eprintln!("Wrote {} pages to {}", pages.len(), file);
+    }
+    Ok(())
+}
+
+fn main() {
+    let matches = App::new("wiki-import-md")
+        .about("Imports a folder of Markdown notes as federated wiki pages.")
+        .arg(
+            Arg::with_name("notes")
+                .long("notes")
+                .short("n")
+                .required(true)
+                .takes_value(true)
+                .value_name("DIR")
+                .help("The folder of notes, searched recursively for .md files."),
+        )
+        .arg(
+            Arg::with_name("pages")
+                .long("pages")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Write a file per page, as in the pages folder of a wiki server."),
+        )
+        .arg(
+            Arg::with_name("export")
+                .long("export")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write all pages to a single file, as the wiki's export.json."),
+        )
+        .group(
+            ArgGroup::with_name("output")
+                .args(&["pages", "export"])
+                .required(true),
+        )
+        .get_matches();
+    if let Err(err) = run(&matches) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
@@ -1,10 +1,22 @@
+use chrono::Utc;
 use clap::{App, Arg};
-use failure::Error;
+use failure::{err_msg, Error};
+use glob::Pattern;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
 use std::process::Command;
-use wiki_rust::{Page, Sitemap};
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+use wiki_rust::assets::Asset;
+use wiki_rust::bundle::{Bundle, BundleStyle};
+use wiki_rust::epub::Epub;
+use wiki_rust::links::{self, internal_links};
+use wiki_rust::pdf::PdfDocument;
+use wiki_rust::render::{document_start, stylesheet, LinkStyle, Renderer, DOCUMENT_END, THEMES};
+use wiki_rust::{site_url, Entry, Page, Sitemap};
 
 // Consider submitting a PR against the webbrowser crate
 // https://github.com/amodm/webbrowser-rs
@@ -14,19 +26,148 @@ use wiki_rust::{Page, Sitemap};
 // Type "start iexplore" and press "Enter" to open Internet Explorer
 // and view its default home screen. Alternatively, type "start firefox,"
 // "start opera" or "start chrome" and press "Enter" to open one of those browsers.
-fn open_browser(url: &str) {
-    let _output = if cfg!(target_os = "windows") {
+// Elsewhere the platform's opener picks the user's default browser. It is spawned
+// rather than waited on, as some browsers keep it running until they are closed.
+fn open_browser(url: &str) -> io::Result<()> {
+    if cfg!(target_os = "windows") {
         Command::new("cmd")
             .args(&["/C", format!("start chrome {}", url).as_str()])
-            .output()
-            .expect("failed to execute process")
+            .output()?;
     } else {
-        Command::new("sh")
-            .arg("-c")
-            .arg("echo hello")
-            .output()
-            .expect("failed to execute process")
+        let opener = if cfg!(target_os = "macos") {
+            "open"
+        } else {
+            "xdg-open"
+        };
+        Command::new(opener).arg(url).spawn()?;
+    }
+    Ok(())
+}
+
+/// How long the server waits for further requests after the last one.
+const IDLE: Duration = Duration::from_secs(5);
+
+/// Serves the document on an ephemeral localhost port and opens it in the browser.
+/// Other paths are the document's assets, which are fetched from the site.
+/// Shuts down once requests stop arriving, or if the document is not requested in time.
+fn serve(html: &str, site: &str, timeout: Duration) -> Result<(), Error> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    listener.set_nonblocking(true)?;
+    let url = format!("http://{}/", listener.local_addr()?);
+    eprintln!("Serving {}", url);
+    if let Err(error) = open_browser(&url) {
+        eprintln!(
+            "Unable to open a browser ({}), open {} to continue.",
+            error, url
+        );
+    }
+    let mut served = false;
+    let mut deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(IDLE))?;
+                match respond(stream, html, site) {
+                    Ok(path) => served = served || path == "/",
+                    Err(error) => eprintln!("Unable to respond: {}", error),
+                }
+                if served {
+                    deadline = Instant::now() + IDLE;
+                }
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(50))
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+    if !served {
+        return Err(err_msg("Timed out waiting for the browser."));
+    }
+    Ok(())
+}
+
+/// Answers a single request, returning the path that was requested.
+fn respond(mut stream: TcpStream, html: &str, site: &str) -> Result<String, Error> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim().len() > 0 {
+        header.clear();
+    }
+    let path = request.split_whitespace().nth(1).unwrap_or("/").to_owned();
+    let (status, mime, body) = if path == "/" {
+        (
+            "200 OK",
+            "text/html; charset=utf-8".to_owned(),
+            html.as_bytes().to_vec(),
+        )
+    } else {
+        // Only paths on the site, so the server cannot be used to fetch from elsewhere.
+        // Urls treat backslashes as slashes, so `/\host` would name another host.
+        let site_url = Url::parse(site)?;
+        let url = Some(&path)
+            .filter(|path| path.starts_with('/') && !path.contains('\\'))
+            .and_then(|path| site_url.join(path).ok())
+            .filter(|url| url.origin() == site_url.origin());
+        let asset = match url {
+            Some(url) => Asset::load(url.as_str(), site),
+            None => Err(err_msg(format!("Not a path on the site: {}", path))),
+        };
+        match asset {
+            Ok(asset) => ("200 OK", asset.mime, asset.data),
+            Err(error) => {
+                eprintln!("{}", error);
+                (
+                    "404 Not Found",
+                    "text/plain".to_owned(),
+                    b"Not found".to_vec(),
+                )
+            }
+        }
     };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        mime,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    Ok(path)
+}
+
+/// Whether the slug is selected by one of the slugs or glob patterns.
+fn selected(slug: &str, patterns: &[Pattern]) -> bool {
+    patterns.len() == 0 || patterns.iter().any(|p| p.matches(slug))
+}
+
+/// Orders pages breadth-first by following links from the start page.
+/// Pages that cannot be reached follow in alphabetical order.
+fn order_by_links(pages: Vec<(Entry, Page)>, start: &str) -> Vec<(Entry, Page)> {
+    let mut remaining: HashMap<String, (Entry, Page)> = pages
+        .into_iter()
+        .map(|(entry, page)| (entry.slug.clone(), (entry, page)))
+        .collect();
+    let mut ordered = Vec::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start.to_owned());
+    while let Some(slug) = queue.pop_front() {
+        if let Some((entry, page)) = remaining.remove(&slug) {
+            for item in &page.story {
+                if let Some(text) = &item.text {
+                    queue.extend(internal_links(text).into_iter().map(links::slug));
+                }
+            }
+            ordered.push((entry, page));
+        }
+    }
+    let mut unreachable: Vec<(Entry, Page)> = remaining.into_iter().map(|(_, v)| v).collect();
+    unreachable.sort_by_key(|(entry, _)| entry.title.to_lowercase());
+    ordered.extend(unreachable);
+    ordered
 }
 
 fn main() -> Result<(), Error> {
@@ -40,45 +181,229 @@ fn main() -> Result<(), Error> {
                 .takes_value(true)
                 .help("The site to format."),
         )
+        .arg(
+            Arg::with_name("order")
+                .long("order")
+                .short("o")
+                .takes_value(true)
+                .possible_values(&["date", "alpha", "links"])
+                .default_value("date")
+                .help("Order pages by date, by title, or by following links from the start page."),
+        )
+        .arg(
+            Arg::with_name("start")
+                .long("start")
+                .takes_value(true)
+                .default_value("welcome-visitors")
+                .help("The slug of the page to start from when ordering by links."),
+        )
+        .arg(
+            Arg::with_name("pages")
+                .long("pages")
+                .short("p")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .help(
+                    "Only print pages whose slug matches. Accepts slugs and globs, e.g. how-to-*.",
+                ),
+        )
+        .arg(
+            Arg::with_name("links")
+                .long("links")
+                .takes_value(true)
+                .possible_values(&["site", "footnotes"])
+                .default_value("site")
+                .help("Render links to pages that are not printed as links to the site or as footnotes."),
+        )
+        .arg(
+            Arg::with_name("no-cover")
+                .long("no-cover")
+                .help("Leave out the cover page."),
+        )
+        .arg(
+            Arg::with_name("no-contents")
+                .long("no-contents")
+                .help("Leave out the table of contents."),
+        )
+        .arg(
+            Arg::with_name("no-index")
+                .long("no-index")
+                .help("Leave out the index of terms."),
+        )
+        .arg(
+            Arg::with_name("history")
+                .long("history")
+                .help("Append the history of each page: when it was created, edits, fork sources and last change."),
+        )
+        .arg(
+            Arg::with_name("theme")
+                .long("theme")
+                .takes_value(true)
+                .possible_values(THEMES)
+                .default_value("default")
+                .help("The built-in theme to style the document with."),
+        )
+        .arg(
+            Arg::with_name("css")
+                .long("css")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("A stylesheet applied after the theme."),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .short("f")
+                .takes_value(true)
+                .possible_values(&["html", "pdf", "epub"])
+                .default_value("html")
+                .help("Print to an HTML document to open in the browser, or straight to a PDF or EPUB."),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .value_name("FILE")
+                .help(
+                    "Where to write the document. HTML is served to the browser unless bundled or given.",
+                ),
+        )
+        .arg(
+            Arg::with_name("bundle")
+                .long("bundle")
+                .takes_value(true)
+                .possible_values(&["html", "zip"])
+                .help(
+                    "Download the images and assets the document uses, to read it offline. \
+                     Writes a single HTML file with them inline, or a zip archive of files.",
+                ),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .default_value("30")
+                .help("Seconds to wait for the browser to request the document."),
+        )
         .get_matches();
-    let site = matches
-        .value_of("site")
-        .expect("Unable to get value for site");
-    // Instead of writing to a file, start a temporary web server
-    // Wait for a single request and then shutdown.
-    // If the request takes too long to arrive, timeout.
-    let mut file = fs::File::create("site.html")?;
-    writeln!(
-        file,
-        "<html>
-            <head></head>
-            <body>
-    "
-    )?;
-    let sitemap = Sitemap::from_url(site).expect("Unable to retrieve or parse sitemap!");
+    let site = site_url(
+        matches
+            .value_of("site")
+            .expect("Unable to get value for site"),
+    );
+    let mut patterns = Vec::new();
+    for pattern in matches.values_of("pages").into_iter().flatten() {
+        patterns.push(Pattern::new(pattern)?);
+    }
+    let sitemap = Sitemap::from_url(&site).expect("Unable to retrieve or parse sitemap!");
+    let mut pages = Vec::new();
     for entry in sitemap.entries {
-        let page = Page::from_site_slug(site, &entry.slug)?;
-        writeln!(file, "<div class=\"page\"><div>{}<div>", page.title)?;
-        writeln!(file, "<div class=\"story\">")?;
-        for item in page.story {
-            writeln!(file, "<div class=\"item\">{}</div>", item.text.unwrap())?;
+        if !selected(&entry.slug, &patterns) {
+            continue;
         }
-        writeln!(file, "</div></div>")?;
-        break;
-    }
-    writeln!(
-        file,
-        "   </body>
-        </html>
-    "
-    )?;
-    drop(file);
-    open_browser(
-        format!(
-            "file://{}/site.html",
-            PathBuf::from(".").canonicalize().unwrap().display()
-        )
-        .as_str(),
-    );
-    Ok(())
+        let page = match Page::from_site_slug(&site, &entry.slug) {
+            Ok(page) => page,
+            Err(err) => {
+                eprintln!("Skipping {}: {}", entry.slug, err);
+                continue;
+            }
+        };
+        pages.push((entry, page));
+    }
+    match matches.value_of("order").unwrap() {
+        "alpha" => pages.sort_by_key(|(entry, _)| entry.title.to_lowercase()),
+        "links" => pages = order_by_links(pages, matches.value_of("start").unwrap()),
+        _ => {}
+    }
+    let mut renderer = Renderer::new(&site);
+    renderer.included = pages.iter().map(|(entry, _)| entry.slug.clone()).collect();
+    if matches.value_of("links") == Some("footnotes") {
+        renderer.link_style = LinkStyle::Footnotes;
+    }
+    let format = matches.value_of("format").unwrap();
+    if matches.is_present("bundle") && format != "html" {
+        return Err(err_msg("Only HTML documents can be bundled."));
+    }
+    if format == "pdf" {
+        let mut document = PdfDocument::new(&renderer);
+        if !matches.is_present("no-cover") {
+            document.cover(&Utc::now().naive_utc());
+        }
+        for (entry, page) in &pages {
+            document.page(&entry.slug, page);
+        }
+        if matches.is_present("history") {
+            document.history(&pages);
+        }
+        let path = matches.value_of("output").unwrap_or("site.pdf");
+        document.write(&mut fs::File::create(path)?)?;
+        eprintln!("Wrote {}", path);
+        return Ok(());
+    }
+    let theme = matches.value_of("theme").unwrap();
+    let mut css = stylesheet(theme).ok_or_else(|| err_msg(format!("Unknown theme: {}", theme)))?;
+    if let Some(path) = matches.value_of("css") {
+        css.push_str(&fs::read_to_string(path)?);
+    }
+    if format == "epub" {
+        renderer.file_extension = Some("xhtml".to_owned());
+        let mut epub = Epub::new(&renderer, &css);
+        let now = Utc::now().naive_utc();
+        if !matches.is_present("no-cover") {
+            epub.cover(&now);
+        }
+        for (entry, page) in &pages {
+            epub.page(&entry.slug, page);
+        }
+        if matches.is_present("history") {
+            epub.history(&pages);
+        }
+        if !matches.is_present("no-index") {
+            epub.index(&pages);
+        }
+        let path = matches.value_of("output").unwrap_or("site.epub");
+        epub.write(fs::File::create(path)?, &now)?;
+        eprintln!("Wrote {}", path);
+        return Ok(());
+    }
+    let mut html = document_start(renderer.site_name(), &css);
+    if !matches.is_present("no-cover") {
+        html.push_str(&renderer.cover(&Utc::now().naive_utc()));
+    }
+    if !matches.is_present("no-contents") {
+        html.push_str(&renderer.contents(&pages));
+    }
+    for (entry, page) in &pages {
+        html.push_str(&renderer.page(&entry.slug, page));
+    }
+    if matches.is_present("history") {
+        html.push_str(&renderer.history(&pages));
+    }
+    if !matches.is_present("no-index") {
+        html.push_str(&renderer.index(&pages));
+    }
+    html.push_str(DOCUMENT_END);
+    if let Some(style) = matches.value_of("bundle") {
+        let (style, default_path) = match style {
+            "zip" => (BundleStyle::Archive, "site.zip"),
+            _ => (BundleStyle::Inline, "site.html"),
+        };
+        let mut bundle = Bundle::new(&site, style);
+        let html = bundle.localize(&html);
+        let path = matches.value_of("output").unwrap_or(default_path);
+        match style {
+            BundleStyle::Archive => bundle.write_archive(&html, fs::File::create(path)?)?,
+            BundleStyle::Inline => fs::write(path, html)?,
+        }
+        eprintln!("Wrote {}", path);
+        return Ok(());
+    }
+    if let Some(path) = matches.value_of("output") {
+        fs::write(path, html)?;
+        eprintln!("Wrote {}", path);
+        return Ok(());
+    }
+    let timeout = matches.value_of("timeout").unwrap().parse::<u64>()?;
+    serve(&html, &site, Duration::from_secs(timeout))
 }
@@ -0,0 +1,273 @@
+use clap::{App, Arg, ArgMatches};
+use failure::{err_msg, Error};
+use regex::{Captures, Regex};
+use serde_json::json;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use wiki_rust::assets::Asset;
+use wiki_rust::format::{escape_xml as escape, iso_date, unescape_xml as unescape};
+use wiki_rust::links::is_slug;
+use wiki_rust::render::{screen_stylesheet, Renderer, THEMES};
+use wiki_rust::{site_url, Entry, Page, Sitemap};
+
+/// Pages the export writes itself, which pages of the site cannot replace.
+const GENERATED: &[&str] = &["index", "recent"];
+
+/// Filters the index page's list of pages using search.json.
+const SEARCH_SCRIPT: &str = r#"<script>
+fetch("search.json").then(r => r.json()).then(pages => {
+  const input = document.getElementById("search");
+  const list = document.getElementById("pages");
+  const all = list.innerHTML;
+  input.addEventListener("input", () => {
+    const terms = input.value.toLowerCase().split(/\s+/).filter(t => t);
+    if (terms.length == 0) {
+      list.innerHTML = all;
+      return;
+    }
+    list.innerHTML = "";
+    for (const page of pages) {
+      const text = (page.title + " " + page.text).toLowerCase();
+      if (terms.every(t => text.includes(t))) {
+        const item = document.createElement("li");
+        const link = document.createElement("a");
+        link.href = page.slug + ".html";
+        link.textContent = page.title;
+        item.appendChild(link);
+        list.appendChild(item);
+      }
+    }
+  });
+});
+</script>
+"#;
+
+/// A page of the static site, linking to the stylesheet and the generated pages.
+fn document(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <link rel=\"icon\" href=\"favicon.png\">\n<link rel=\"stylesheet\" href=\"style.css\">\n\
+         </head>\n<body>\n<nav class=\"site\"><a href=\"index.html\">Index</a> \
+         <a href=\"recent.html\">Recent changes</a></nav>\n{}</body>\n</html>\n",
+        escape(title),
+        body
+    )
+}
+
+/// Copies the site's own assets into the export and points the html at the copies.
+/// Images on other sites, or inline in the page, are left as they are.
+struct AssetCopier<'a> {
+    site: &'a str,
+    out: &'a Path,
+    copied: HashSet<String>,
+}
+
+impl<'a> AssetCopier<'a> {
+    fn localize(&mut self, html: &str) -> String {
+        let source = Regex::new(r#"\bsrc="([^"]*)""#).unwrap();
+        let favicon = format!("{}/favicon.png", self.site);
+        source
+            .replace_all(html, |captures: &Captures| {
+                let url = unescape(&captures[1]);
+                if url == favicon {
+                    return "src=\"favicon.png\"".to_owned();
+                }
+                let outside = url.contains('\\') || url.split('/').any(|part| part == "..");
+                if !url.starts_with('/') || url.starts_with("//") || outside {
+                    return captures[0].to_owned();
+                }
+                let file = url.split(|c| c == '?' || c == '#').next().unwrap();
+                let path = file.trim_start_matches('/').to_owned();
+                if self.copied.insert(path.clone()) {
+                    if let Err(error) = self.copy(&url, &path) {
+                        eprintln!("Unable to copy {}: {}", url, error);
+                    }
+                }
+                format!("src=\"{}\"", escape(&path))
+            })
+            .into_owned()
+    }
+
+    fn copy(&self, url: &str, path: &str) -> Result<(), Error> {
+        let asset = Asset::load(url, self.site)?;
+        let file = self.out.join(path);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(file, asset.data)?;
+        Ok(())
+    }
+}
+
+/// The words of the page, for finding it from the index page.
+fn search_text(page: &Page) -> String {
+    page.story
+        .iter()
+        .filter_map(|item| item.text.as_ref())
+        .map(|text| text.replace("[[", "").replace("]]", ""))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn index_page(pages: &[(Entry, Page)], name: &str) -> String {
+    let mut sorted: Vec<&(Entry, Page)> = pages.iter().collect();
+    sorted.sort_by_key(|(entry, _)| entry.title.to_lowercase());
+    let mut body = format!(
+        "<div class=\"index\">\n<h1>{}</h1>\n\
+         <input id=\"search\" type=\"search\" placeholder=\"Search\">\n<ul id=\"pages\">\n",
+        escape(name)
+    );
+    for (entry, page) in sorted {
+        body.push_str(&format!(
+            "<li><a href=\"{}.html\">{}</a></li>\n",
+            escape(&entry.slug),
+            escape(&page.title)
+        ));
+    }
+    body.push_str("</ul>\n</div>\n");
+    body.push_str(SEARCH_SCRIPT);
+    document(name, &body)
+}
+
+fn recent_page(pages: &[(Entry, Page)], limit: usize) -> String {
+    let mut sorted: Vec<&(Entry, Page)> = pages.iter().collect();
+    sorted.sort_by(|(a, _), (b, _)| b.date.cmp(&a.date));
+    let mut body = String::from("<div class=\"recent\">\n<h1>Recent changes</h1>\n<ul>\n");
+    for (entry, page) in sorted.into_iter().take(limit) {
+        body.push_str(&format!(
+            "<li>{} <a href=\"{}.html\">{}</a><p>{}</p></li>\n",
+            entry.date.format("%Y-%m-%d"),
+            escape(&entry.slug),
+            escape(&page.title),
+            escape(&entry.synopsis)
+        ));
+    }
+    body.push_str("</ul>\n</div>\n");
+    document("Recent changes", &body)
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let site = site_url(matches.value_of("site").unwrap());
+    let sitemap = Sitemap::from_url(&site)?;
+    let out = PathBuf::from(matches.value_of("out").unwrap_or(&sitemap.name));
+    let limit = matches.value_of("recent").unwrap();
+    let limit = limit
+        .parse::<usize>()
+        .map_err(|_| err_msg(format!("Invalid value for --recent: {}", limit)))?;
+    let theme = matches.value_of("theme").unwrap();
+    let mut css =
+        screen_stylesheet(theme).ok_or_else(|| err_msg(format!("Unknown theme: {}", theme)))?;
+    if let Some(path) = matches.value_of("css") {
+        css.push_str(&fs::read_to_string(path)?);
+    }
+
+    let mut pages = Vec::new();
+    for entry in sitemap.entries {
+        // Slugs come from the site, so only those that make safe file names are exported.
+        if !is_slug(&entry.slug) || GENERATED.contains(&entry.slug.as_str()) {
+            eprintln!("Skipping {}: not usable as a file name", entry.slug);
+            continue;
+        }
+        let page = match Page::from_site_slug(&site, &entry.slug) {
+            Ok(page) => page,
+            Err(err) => {
+                eprintln!("Skipping {}: {}", entry.slug, err);
+                continue;
+            }
+        };
+        pages.push((entry, page));
+    }
+    let mut renderer = Renderer::new(&site);
+    renderer.file_extension = Some("html".to_owned());
+    renderer.included = pages.iter().map(|(entry, _)| entry.slug.clone()).collect();
+
+    fs::create_dir_all(&out)?;
+    let mut copier = AssetCopier {
+        site: &renderer.site,
+        out: &out,
+        copied: HashSet::new(),
+    };
+    match Asset::load("/favicon.png", &site) {
+        Ok(favicon) => fs::write(out.join("favicon.png"), favicon.data)?,
+        Err(error) => eprintln!("Unable to copy favicon: {}", error),
+    }
+    fs::write(out.join("style.css"), css)?;
+    let mut search = Vec::new();
+    for (entry, page) in &pages {
+        let body = format!(
+            "{}<p class=\"source\">Last changed {}. <a href=\"{}/view/{}\">View on {}</a></p>\n",
+            renderer.page(&entry.slug, page),
+            entry.date.format("%B %e, %Y"),
+            escape(&site),
+            escape(&entry.slug),
+            escape(renderer.site_name())
+        );
+        let html = copier.localize(&document(&page.title, &body));
+        fs::write(out.join(format!("{}.html", entry.slug)), html)?;
+        search.push(json!({
+            "slug": entry.slug,
+            "title": page.title,
+            "date": iso_date(&entry.date),
+            "synopsis": entry.synopsis,
+            "text": search_text(page),
+        }));
+    }
+    fs::write(
+        out.join("index.html"),
+        index_page(&pages, renderer.site_name()),
+    )?;
+    fs::write(out.join("recent.html"), recent_page(&pages, limit))?;
+    fs::write(out.join("search.json"), serde_json::to_string(&search)?)?;
+    eprintln!("Exported {} pages to {}", pages.len(), out.display());
+    Ok(())
+}
+
+fn main() {
+    let matches = App::new("wiki-static")
+        .about("Exports a federated wiki site as static HTML files.")
+        .arg(
+            Arg::with_name("site")
+                .long("site")
+                .short("s")
+                .required(true)
+                .takes_value(true)
+                .help("The site to export."),
+        )
+        .arg(
+            Arg::with_name("out")
+                .long("out")
+                .short("o")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("The directory to export to. Defaults to the site's name."),
+        )
+        .arg(
+            Arg::with_name("recent")
+                .long("recent")
+                .takes_value(true)
+                .default_value("50")
+                .help("Number of pages to list on the recent changes page."),
+        )
+        .arg(
+            Arg::with_name("theme")
+                .long("theme")
+                .takes_value(true)
+                .possible_values(THEMES)
+                .default_value("default")
+                .help("The built-in theme to style the pages with."),
+        )
+        .arg(
+            Arg::with_name("css")
+                .long("css")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("A stylesheet applied after the theme."),
+        )
+        .get_matches();
+    if let Err(err) = run(&matches) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
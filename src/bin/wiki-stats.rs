@@ -0,0 +1,187 @@
+use clap::{App, Arg, ArgMatches};
+use failure::{err_msg, Error};
+use std::process::exit;
+use wiki_rust::config::{load_neighborhood, DEFAULT_ROSTER_SITE, DEFAULT_ROSTER_SLUG};
+use wiki_rust::stats::{ForkCount, PageCount, Stats};
+
+fn parse_days(matches: &ArgMatches, name: &str) -> Result<i64, Error> {
+    let value = matches.value_of(name).unwrap();
+    value
+        .parse::<i64>()
+        .map_err(|_| err_msg(format!("Invalid number of days for --{}: {}", name, value)))
+}
+
+fn print_counts(title: &str, counts: &[PageCount]) {
+    println!("{}", title);
+    for count in counts {
+        println!("\t{:>6}  {} ({})", count.count, count.title, count.site);
+    }
+    println!();
+}
+
+fn print_forks(forks: &[ForkCount]) {
+    println!("Most forked pages (sites holding a fork)");
+    for fork in forks {
+        print!(
+            "\t{:>6}  {} ({})",
+            fork.count,
+            fork.title,
+            fork.sites.join(", ")
+        );
+        if fork.sources.len() > 0 {
+            print!(" from {}", fork.sources.join(", "));
+        }
+        println!();
+    }
+    println!();
+}
+
+fn print_table(stats: &Stats) {
+    println!("Pages per site");
+    for site in &stats.sites {
+        println!(
+            "\t{:>6}  {} (last edit {})",
+            site.pages,
+            site.site,
+            site.last_edit
+                .as_ref()
+                .map(|d| d.as_str())
+                .unwrap_or("never")
+        );
+    }
+    println!();
+    println!("Edits per day (last {} days)", stats.window_days);
+    for (day, count) in &stats.edits_per_day {
+        println!("\t{:>6}  {}", count, day);
+    }
+    println!();
+    println!("Edits per week (last {} days)", stats.window_days);
+    for (week, count) in &stats.edits_per_week {
+        println!("\t{:>6}  {}", count, week);
+    }
+    println!();
+    print_counts("Most edited pages", &stats.most_edited);
+    print_forks(&stats.most_forked);
+    println!(
+        "Item types (pages changed in the last {} days)",
+        stats.window_days
+    );
+    for (item_type, count) in &stats.item_types_in_window {
+        println!("\t{:>6}  {}", count, item_type);
+    }
+    println!();
+    println!("Dormant sites (no edits in {} days)", stats.dormant_days);
+    for site in &stats.dormant {
+        println!("\t{}", site);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    if !["pod", "neighborhood", "site"]
+        .iter()
+        .any(|name| matches.is_present(name))
+    {
+        return Err(err_msg(
+            "One of --pod, --neighborhood or --site is required.",
+        ));
+    }
+    let neighborhood = load_neighborhood(matches)?;
+    let top = matches.value_of("top").unwrap();
+    let top = top
+        .parse::<usize>()
+        .map_err(|_| err_msg(format!("Invalid value for --top: {}", top)))?;
+    let stats = Stats::from_neighborhood(
+        &neighborhood,
+        parse_days(matches, "days")?,
+        parse_days(matches, "dormant")?,
+        top,
+    )?;
+    match matches.value_of("format").unwrap() {
+        "json" => println!("{}", serde_json::to_string_pretty(&stats)?),
+        _ => print_table(&stats),
+    }
+    Ok(())
+}
+
+fn main() {
+    let matches = App::new("wiki-stats")
+        .about("Activity statistics for fed wiki sites.")
+        .arg(
+            Arg::with_name("pod")
+                .long("pod")
+                .short("p")
+                .help("Compute statistics for the sites listed on the roster page."),
+        )
+        .arg(
+            Arg::with_name("roster-site")
+                .long("roster-site")
+                .takes_value(true)
+                .default_value(DEFAULT_ROSTER_SITE)
+                .help("The site holding the roster page used by --pod."),
+        )
+        .arg(
+            Arg::with_name("roster-slug")
+                .long("roster-slug")
+                .takes_value(true)
+                .default_value(DEFAULT_ROSTER_SLUG)
+                .help("The slug of the roster page used by --pod."),
+        )
+        .arg(
+            Arg::with_name("neighborhood")
+                .long("neighborhood")
+                .short("n")
+                .takes_value(true)
+                .help("Compute statistics for a neighborhood named in the config file."),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .help("The config file defining neighborhoods."),
+        )
+        .arg(
+            Arg::with_name("site")
+                .long("site")
+                .short("s")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Compute statistics for the specified site. May be repeated. Filters the roster with --pod or --neighborhood."),
+        )
+        .arg(
+            Arg::with_name("days")
+                .long("days")
+                .short("d")
+                .takes_value(true)
+                .default_value("30")
+                .help("Only count journal activity within the number of days specified."),
+        )
+        .arg(
+            Arg::with_name("dormant")
+                .long("dormant")
+                .takes_value(true)
+                .default_value("90")
+                .help("Report sites with no edits in the number of days specified."),
+        )
+        .arg(
+            Arg::with_name("top")
+                .long("top")
+                .takes_value(true)
+                .default_value("10")
+                .help("Number of pages to list as most edited and most forked."),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .short("f")
+                .takes_value(true)
+                .possible_values(&["table", "json"])
+                .default_value("table")
+                .help("Output format."),
+        )
+        .get_matches();
+    if let Err(err) = run(&matches) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
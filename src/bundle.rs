@@ -0,0 +1,100 @@
+use crate::assets::Asset;
+use crate::format::{escape_xml as escape, unescape_xml as unescape};
+use failure::Error;
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::io::{Seek, Write};
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+/// Where a bundle keeps the assets of a document.
+#[derive(Clone, Copy, PartialEq)]
+pub enum BundleStyle {
+    /// Inline as data urls, so the document is a single file.
+    Inline,
+    /// As files beside the document, in a zip archive.
+    Archive,
+}
+
+/// Downloads the images, favicons and assets a rendered document refers to,
+/// so it can be read once the sites it came from are gone.
+pub struct Bundle {
+    pub site: String,
+    pub style: BundleStyle,
+    /// Assets by the url they were loaded from, with their path in the archive.
+    assets: HashMap<String, (String, Asset)>,
+}
+
+impl Bundle {
+    pub fn new(site: &str, style: BundleStyle) -> Bundle {
+        Bundle {
+            site: site.trim_end_matches('/').to_owned(),
+            style,
+            assets: HashMap::new(),
+        }
+    }
+
+    /// Rewrites image sources, and links to files among the site's assets,
+    /// to the bundled copies. Urls that cannot be loaded are left as they are.
+    pub fn localize(&mut self, html: &str) -> String {
+        let source = Regex::new(r#"\b(src|href)="([^"]*)""#).unwrap();
+        source
+            .replace_all(html, |captures: &Captures| {
+                let url = unescape(&captures[2]);
+                if !self.is_asset(&captures[1], &url) {
+                    return captures[0].to_owned();
+                }
+                match self.load(&url) {
+                    Some(local) => format!("{}=\"{}\"", &captures[1], escape(&local)),
+                    None => captures[0].to_owned(),
+                }
+            })
+            .into_owned()
+    }
+
+    /// Every image source, and links to files uploaded to the site. Browsers will
+    /// not follow links to data urls, so links are only bundled in archives.
+    fn is_asset(&self, attribute: &str, url: &str) -> bool {
+        if url.len() == 0 || url.starts_with("data:") || url.starts_with('#') {
+            return false;
+        }
+        attribute == "src"
+            || self.style == BundleStyle::Archive
+                && (url.starts_with("/assets/")
+                    || url.starts_with(&format!("{}/assets/", self.site)))
+    }
+
+    fn load(&mut self, url: &str) -> Option<String> {
+        if !self.assets.contains_key(url) {
+            match Asset::load(url, &self.site) {
+                Ok(asset) => {
+                    let path = format!("assets/{}.{}", self.assets.len() + 1, asset.extension());
+                    self.assets.insert(url.to_owned(), (path, asset));
+                }
+                Err(error) => {
+                    eprintln!("Unable to bundle {}: {}", url, error);
+                    return None;
+                }
+            }
+        }
+        let (path, asset) = &self.assets[url];
+        Some(match self.style {
+            BundleStyle::Inline => asset.data_url(),
+            BundleStyle::Archive => path.clone(),
+        })
+    }
+
+    /// Writes the document as index.html in a zip archive, with the assets it uses.
+    pub fn write_archive<W: Write + Seek>(&self, html: &str, out: W) -> Result<(), Error> {
+        let mut zip = ZipWriter::new(out);
+        let options = FileOptions::default();
+        zip.start_file("index.html", options)?;
+        zip.write_all(html.as_bytes())?;
+        for (path, asset) in self.assets.values() {
+            zip.start_file(path.as_str(), options)?;
+            zip.write_all(&asset.data)?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+}
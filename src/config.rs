@@ -0,0 +1,130 @@
+use crate::sites::{read_opml, read_site_list};
+use crate::{matches_filter, site_url, Neighborhood};
+use clap::ArgMatches;
+use failure::{err_msg, Error};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use url::Url;
+
+pub const DEFAULT_ROSTER_SITE: &str = "http://code.fed.wiki";
+pub const DEFAULT_ROSTER_SLUG: &str = "our-learning-pod";
+
+/// A named neighborhood: the sites listed on a roster page, plus any extra sites.
+#[derive(Deserialize)]
+pub struct NeighborhoodConfig {
+    pub roster_site: Option<String>,
+    pub roster_slug: Option<String>,
+    #[serde(default)]
+    pub sites: Vec<String>,
+}
+
+impl NeighborhoodConfig {
+    pub fn load(&self, site_filter: &[&str]) -> Result<Neighborhood, Error> {
+        let mut neighborhood = Neighborhood::new();
+        if self.roster_site.is_some() || self.roster_slug.is_some() {
+            neighborhood.add_roster(
+                &site_url(self.roster_site.as_deref().unwrap_or(DEFAULT_ROSTER_SITE)),
+                self.roster_slug.as_deref().unwrap_or(DEFAULT_ROSTER_SLUG),
+                site_filter,
+            )?;
+        }
+        for site in &self.sites {
+            if !matches_filter(site, site_filter) {
+                continue;
+            }
+            neighborhood.add(&site_url(site))?;
+        }
+        Ok(neighborhood)
+    }
+}
+
+/// User configuration, read from `config.yaml` in the wiki-rust config dir.
+#[derive(Deserialize, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub neighborhoods: HashMap<String, NeighborhoodConfig>,
+}
+
+impl Config {
+    pub fn default_path() -> PathBuf {
+        dirs::config_dir()
+            .expect("Unable to find config dir.")
+            .join("wiki-rust")
+            .join("config.yaml")
+    }
+
+    pub fn from_config(config: &str) -> Result<Config, Error> {
+        let config = config.replace("~", dirs::home_dir().unwrap().to_str().unwrap());
+        let reader = File::open(config)?;
+        let config = serde_yaml::from_reader(reader)?;
+        Ok(config)
+    }
+
+    /// Loads the config at the given path, or the default path if it exists.
+    pub fn load(path: Option<&str>) -> Result<Config, Error> {
+        match path {
+            Some(path) => Config::from_config(path),
+            None => {
+                let path = Config::default_path();
+                if !path.exists() {
+                    return Ok(Config::default());
+                }
+                Config::from_config(path.to_str().unwrap())
+            }
+        }
+    }
+
+    pub fn neighborhood(&self, name: &str) -> Result<&NeighborhoodConfig, Error> {
+        self.neighborhoods
+            .get(name)
+            .ok_or_else(|| err_msg(format!("No neighborhood named {} in config.", name)))
+    }
+}
+
+/// Sites named with --site and listed in --sites-file and --opml files.
+fn listed_sites(matches: &ArgMatches) -> Result<Vec<String>, Error> {
+    let mut sites: Vec<String> = matches
+        .values_of("site")
+        .map(|sites| sites.map(|s| s.to_owned()).collect())
+        .unwrap_or_default();
+    for path in matches.values_of("sites-file").into_iter().flatten() {
+        sites.extend(read_site_list(&fs::read_to_string(path)?));
+    }
+    for path in matches.values_of("opml").into_iter().flatten() {
+        sites.extend(read_opml(&fs::read_to_string(path)?));
+    }
+    Ok(sites)
+}
+
+/// The neighborhood chosen by the options the tools share: --neighborhood and
+/// --config, --pod with --roster-site and --roster-slug, and --site, --sites-file
+/// and --opml. With --pod or --neighborhood, sites given with --site filter the roster.
+pub fn load_neighborhood(matches: &ArgMatches) -> Result<Neighborhood, Error> {
+    let mut neighborhood = Neighborhood::new();
+    let site_filter: Vec<&str> = matches.values_of("site").into_iter().flatten().collect();
+    let mut sites = listed_sites(matches)?;
+    if let Some(name) = matches.value_of("neighborhood") {
+        let config = Config::load(matches.value_of("config"))?;
+        neighborhood = config.neighborhood(name)?.load(&site_filter)?;
+        sites.retain(|site| !site_filter.contains(&site.as_str()));
+    } else if matches.is_present("pod") {
+        neighborhood.add_roster(
+            &site_url(
+                matches
+                    .value_of("roster-site")
+                    .unwrap_or(DEFAULT_ROSTER_SITE),
+            ),
+            matches
+                .value_of("roster-slug")
+                .unwrap_or(DEFAULT_ROSTER_SLUG),
+            &site_filter,
+        )?;
+        sites.retain(|site| !site_filter.contains(&site.as_str()));
+    }
+    for site in sites {
+        neighborhood.add(Url::parse(&site_url(&site))?.as_str())?;
+    }
+    Ok(neighborhood)
+}
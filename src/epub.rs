@@ -0,0 +1,237 @@
+use crate::assets::Asset;
+use crate::format::{escape_xml as escape, unescape_xml as unescape};
+use crate::links::is_slug;
+use crate::render::Renderer;
+use crate::{Entry, Page};
+use chrono::NaiveDateTime;
+use failure::Error;
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::io::{Seek, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const CONTAINER: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+<rootfiles>\n\
+<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\n\
+</rootfiles>\n\
+</container>\n";
+
+struct Chapter {
+    file: String,
+    title: String,
+    body: String,
+}
+
+/// Collects pages of a site as the chapters of an EPUB.
+/// The renderer should name pages as files with the xhtml extension,
+/// so that links between chapters point to their files.
+pub struct Epub<'a> {
+    renderer: &'a Renderer,
+    css: String,
+    chapters: Vec<Chapter>,
+    /// Images copied into the book, by the url they were loaded from.
+    images: HashMap<String, (String, Asset)>,
+    /// The chapters of wiki pages, which the navigation document lists.
+    contents: Vec<usize>,
+}
+
+impl<'a> Epub<'a> {
+    pub fn new(renderer: &'a Renderer, css: &str) -> Epub<'a> {
+        Epub {
+            renderer,
+            css: css.to_owned(),
+            chapters: Vec::new(),
+            images: HashMap::new(),
+            contents: Vec::new(),
+        }
+    }
+
+    pub fn cover(&mut self, date: &NaiveDateTime) {
+        let body = self.renderer.cover(date);
+        self.add("_cover", "Cover", body);
+    }
+
+    /// Chapters are named for their slugs, which come from the site, so odd ones
+    /// are numbered instead. Links never lead to those, as titles have usable slugs.
+    pub fn page(&mut self, slug: &str, page: &Page) {
+        self.contents.push(self.chapters.len());
+        let body = self.renderer.page(slug, page);
+        let name = if is_slug(slug) {
+            slug.to_owned()
+        } else {
+            format!("_page{}", self.chapters.len() + 1)
+        };
+        self.add(&name, &page.title, body);
+    }
+
+    pub fn history(&mut self, pages: &[(Entry, Page)]) {
+        let body = self.renderer.history(pages);
+        self.add("_history", "History", body);
+    }
+
+    pub fn index(&mut self, pages: &[(Entry, Page)]) {
+        let body = self.renderer.index(pages);
+        self.add("_index", "Index", body);
+    }
+
+    fn add(&mut self, name: &str, title: &str, body: String) {
+        let body = self.embed_images(&xhtml(&body));
+        self.chapters.push(Chapter {
+            file: format!("{}.xhtml", name),
+            title: title.to_owned(),
+            body,
+        });
+    }
+
+    /// Copies the images a chapter shows into the book, since readers are often
+    /// offline. Images that cannot be loaded are left out.
+    fn embed_images(&mut self, body: &str) -> String {
+        let tag = Regex::new(r#"<img\b[^>]*>"#).unwrap();
+        let source = Regex::new(r#"\bsrc="([^"]*)""#).unwrap();
+        let mut html = String::new();
+        let mut last = 0;
+        for found in tag.find_iter(body) {
+            html.push_str(&body[last..found.start()]);
+            last = found.end();
+            let url = match source.captures(found.as_str()) {
+                Some(captures) => unescape(&captures[1]),
+                None => continue,
+            };
+            if !self.images.contains_key(&url) {
+                match Asset::load(&url, &self.renderer.site) {
+                    Ok(asset) if asset.mime.starts_with("image/") && asset.extension() != "bin" => {
+                        let path =
+                            format!("images/{}.{}", self.images.len() + 1, asset.extension());
+                        self.images.insert(url.clone(), (path, asset));
+                    }
+                    Ok(asset) => {
+                        eprintln!("Leaving out {} image: {}", asset.mime, url);
+                        continue;
+                    }
+                    Err(error) => {
+                        eprintln!("Unable to load image: {}", error);
+                        continue;
+                    }
+                }
+            }
+            let path = &self.images[&url].0;
+            html.push_str(&source.replace(found.as_str(), |_: &Captures| {
+                format!("src=\"{}\"", escape(path))
+            }));
+        }
+        html.push_str(&body[last..]);
+        html
+    }
+
+    /// Writes the book, with the site's name and the given date as its metadata.
+    /// The date is part of the book's identifier, so readers keep each export apart.
+    pub fn write<W: Write + Seek>(&self, out: W, date: &NaiveDateTime) -> Result<(), Error> {
+        let mut zip = ZipWriter::new(out);
+        // Readers identify the file by an uncompressed mimetype entry that comes first.
+        zip.start_file(
+            "mimetype",
+            FileOptions::default().compression_method(CompressionMethod::Stored),
+        )?;
+        zip.write_all(b"application/epub+zip")?;
+        let options = FileOptions::default();
+        zip.start_file("META-INF/container.xml", options)?;
+        zip.write_all(CONTAINER.as_bytes())?;
+        zip.start_file("OEBPS/content.opf", options)?;
+        zip.write_all(self.package(date).as_bytes())?;
+        zip.start_file("OEBPS/nav.xhtml", options)?;
+        zip.write_all(self.navigation().as_bytes())?;
+        zip.start_file("OEBPS/style.css", options)?;
+        zip.write_all(self.css.as_bytes())?;
+        for chapter in &self.chapters {
+            zip.start_file(format!("OEBPS/{}", chapter.file), options)?;
+            zip.write_all(document(&chapter.title, &chapter.body).as_bytes())?;
+        }
+        for (path, asset) in self.images.values() {
+            zip.start_file(format!("OEBPS/{}", path), options)?;
+            zip.write_all(&asset.data)?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+
+    fn package(&self, date: &NaiveDateTime) -> String {
+        let mut manifest = String::from(
+            "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+             <item id=\"css\" href=\"style.css\" media-type=\"text/css\"/>\n",
+        );
+        let mut spine = String::new();
+        for (number, chapter) in self.chapters.iter().enumerate() {
+            manifest.push_str(&format!(
+                "<item id=\"chapter-{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+                number + 1,
+                escape(&chapter.file)
+            ));
+            spine.push_str(&format!("<itemref idref=\"chapter-{}\"/>\n", number + 1));
+        }
+        let mut images: Vec<&(String, Asset)> = self.images.values().collect();
+        images.sort_by_key(|(path, _)| path.clone());
+        for (number, (path, asset)) in images.iter().enumerate() {
+            manifest.push_str(&format!(
+                "<item id=\"image-{}\" href=\"{}\" media-type=\"{}\"/>\n",
+                number + 1,
+                escape(path),
+                escape(&asset.mime)
+            ));
+        }
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"id\">\n\
+             <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+             <dc:identifier id=\"id\">{site}#{modified}</dc:identifier>\n\
+             <dc:title>{name}</dc:title>\n\
+             <dc:creator>{name}</dc:creator>\n\
+             <dc:source>{site}</dc:source>\n\
+             <dc:language>en</dc:language>\n\
+             <dc:date>{date}</dc:date>\n\
+             <meta property=\"dcterms:modified\">{modified}</meta>\n\
+             </metadata>\n<manifest>\n{manifest}</manifest>\n<spine>\n{spine}</spine>\n</package>\n",
+            site = escape(&self.renderer.site),
+            name = escape(self.renderer.site_name()),
+            date = date.format("%Y-%m-%d"),
+            modified = date.format("%Y-%m-%dT%H:%M:%SZ"),
+            manifest = manifest,
+            spine = spine
+        )
+    }
+
+    /// The table of contents readers show, listing the wiki pages in reading order.
+    fn navigation(&self) -> String {
+        let mut nav = String::from("<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n");
+        for &number in &self.contents {
+            let chapter = &self.chapters[number];
+            nav.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                escape(&chapter.file),
+                escape(&chapter.title)
+            ));
+        }
+        nav.push_str("</ol>\n</nav>\n");
+        document("Contents", &nav)
+    }
+}
+
+fn document(title: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" \
+         lang=\"en\" xml:lang=\"en\">\n<head>\n<meta charset=\"utf-8\"/>\n<title>{}</title>\n\
+         <link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        body
+    )
+}
+
+/// Closes the void elements of rendered HTML, and replaces the one named
+/// entity it uses that XML lacks, so that chapters parse as XHTML.
+fn xhtml(html: &str) -> String {
+    let void = Regex::new(r"<(area|br|col|hr|img|wbr)\b([^>]*?)\s*/?>").unwrap();
+    void.replace_all(html, "<$1$2/>")
+        .replace("&nbsp;", "&#160;")
+}
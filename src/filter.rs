@@ -0,0 +1,146 @@
+use crate::{Change, Entry, Page};
+use chrono::NaiveDateTime;
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Selects recent changes by topic and by what happened to the page.
+/// Only the pattern works from the sitemap; the other filters need the page.
+pub struct ChangeFilter {
+    pub pattern: Option<Regex>,
+    pub text: Option<String>,
+    pub actions: Vec<String>,
+    pub item_types: Vec<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
+
+impl ChangeFilter {
+    pub fn new(since: Option<NaiveDateTime>, until: Option<NaiveDateTime>) -> ChangeFilter {
+        ChangeFilter {
+            pattern: None,
+            text: None,
+            actions: Vec::new(),
+            item_types: Vec::new(),
+            since,
+            until,
+        }
+    }
+
+    pub fn needs_page(&self) -> bool {
+        self.text.is_some() || self.actions.len() > 0 || self.item_types.len() > 0
+    }
+
+    /// Matches the pattern against the entry's title and synopsis.
+    pub fn matches_entry(&self, entry: &Entry) -> bool {
+        match &self.pattern {
+            Some(pattern) => pattern.is_match(&entry.title) || pattern.is_match(&entry.synopsis),
+            None => true,
+        }
+    }
+
+    pub fn matches_page(&self, page: &Page) -> bool {
+        if let Some(text) = &self.text {
+            let text = text.to_lowercase();
+            let found = page.title.to_lowercase().contains(&text)
+                || page.story.iter().any(|item| {
+                    item.text
+                        .as_ref()
+                        .map(|t| t.to_lowercase().contains(&text))
+                        .unwrap_or(false)
+                });
+            if !found {
+                return false;
+            }
+        }
+        let changes: Vec<&Change> = page.journal.iter().filter(|c| self.in_window(c)).collect();
+        if self.actions.len() > 0 && !changes.iter().any(|c| self.actions.contains(&c.r#type)) {
+            return false;
+        }
+        if self.item_types.len() > 0 && !self.gained_item_type(page) {
+            return false;
+        }
+        true
+    }
+
+    fn in_window(&self, change: &Change) -> bool {
+        self.since.map(|s| change.date >= s).unwrap_or(true)
+            && self.until.map(|u| change.date <= u).unwrap_or(true)
+    }
+
+    /// Whether an item of one of the types was created, added or given the type
+    /// by an edit within the window. The client adds new items as factories and
+    /// then edits them into their type, so the journal is replayed to see each
+    /// item's type before the change.
+    fn gained_item_type(&self, page: &Page) -> bool {
+        let mut types: HashMap<String, String> = HashMap::new();
+        for change in &page.journal {
+            let items = match change.r#type.as_str() {
+                "create" => change.created_story(),
+                "add" | "edit" => change.story_item().into_iter().collect(),
+                "remove" => {
+                    if let Some(id) = &change.id {
+                        types.remove(id);
+                    }
+                    Vec::new()
+                }
+                _ => Vec::new(),
+            };
+            for item in items {
+                let gained = types.get(&item.id) != Some(&item.r#type)
+                    && self.item_types.contains(&item.r#type);
+                if gained && self.in_window(change) {
+                    return true;
+                }
+                types.insert(item.id, item.r#type);
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(journal: serde_json::Value) -> Page {
+        serde_json::from_value(serde_json::json!({
+            "title": "A Page",
+            "story": [],
+            "journal": journal,
+        }))
+        .unwrap()
+    }
+
+    fn video_filter() -> ChangeFilter {
+        let mut filter = ChangeFilter::new(None, None);
+        filter.item_types = vec!["video".to_owned()];
+        filter
+    }
+
+    #[test]
+    fn factory_edited_into_a_type_is_a_gain() {
+        let page = page(serde_json::json!([
+            {"type": "create", "date": 1000, "item": {"title": "A Page", "story": []}},
+            {"type": "add", "id": "a1", "date": 2000, "item": {"type": "factory", "id": "a1"}},
+            {"type": "edit", "id": "a1", "date": 3000,
+             "item": {"type": "video", "id": "a1", "text": "YOUTUBE abc"}},
+        ]));
+        assert!(video_filter().matches_page(&page));
+    }
+
+    #[test]
+    fn editing_an_item_of_the_type_is_not_a_gain() {
+        let page = page(serde_json::json!([
+            {"type": "create", "date": 1000, "item": {"title": "A Page", "story": [
+                {"type": "video", "id": "a1", "text": "YOUTUBE abc"}
+            ]}},
+            {"type": "edit", "id": "a1", "date": 3000,
+             "item": {"type": "video", "id": "a1", "text": "YOUTUBE def"}},
+        ]));
+        let mut filter = video_filter();
+        filter.since = Some(NaiveDateTime::from_timestamp(2, 0));
+        assert!(!filter.matches_page(&page));
+        filter.since = None;
+        assert!(filter.matches_page(&page));
+    }
+}
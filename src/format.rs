@@ -0,0 +1,242 @@
+use crate::RecentChange;
+use chrono::{NaiveDateTime, Utc};
+use failure::{err_msg, Error};
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+
+/// A recent change flattened for machine readable output.
+#[derive(Serialize, Clone)]
+pub struct ChangeRecord {
+    pub site: String,
+    pub slug: String,
+    pub title: String,
+    pub date: String,
+    pub synopsis: String,
+    pub url: String,
+}
+
+impl<'a> From<&RecentChange<'a>> for ChangeRecord {
+    fn from(change: &RecentChange<'a>) -> Self {
+        ChangeRecord {
+            site: change.site.name.clone(),
+            slug: change.entry.slug.clone(),
+            title: change.entry.title.clone(),
+            date: iso_date(&change.entry.date),
+            synopsis: change.entry.synopsis.clone(),
+            url: format!("{}/{}.html", change.site.url, change.entry.slug),
+        }
+    }
+}
+
+pub fn iso_date(date: &NaiveDateTime) -> String {
+    date.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    Jsonl,
+    Csv,
+    Markdown,
+    Atom,
+}
+
+pub const FORMATS: &[&str] = &["text", "json", "jsonl", "csv", "markdown", "atom"];
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<Format, Error> {
+        match format {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            "markdown" => Ok(Format::Markdown),
+            "atom" => Ok(Format::Atom),
+            _ => Err(err_msg(format!("Unknown format: {}", format))),
+        }
+    }
+}
+
+impl Format {
+    pub fn write(&self, out: &mut dyn Write, records: &[ChangeRecord]) -> Result<(), Error> {
+        match self {
+            Format::Text => {
+                for record in records {
+                    writeln!(out, "{}\t{}\t{}", record.date, record.site, record.title)?;
+                }
+            }
+            Format::Json => writeln!(out, "{}", serde_json::to_string_pretty(records)?)?,
+            Format::Jsonl => {
+                for record in records {
+                    writeln!(out, "{}", serde_json::to_string(record)?)?;
+                }
+            }
+            Format::Csv => {
+                writeln!(out, "site,slug,title,date,synopsis,url")?;
+                for record in records {
+                    let fields = [
+                        &record.site,
+                        &record.slug,
+                        &record.title,
+                        &record.date,
+                        &record.synopsis,
+                        &record.url,
+                    ];
+                    let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+                    writeln!(out, "{}", fields.join(","))?;
+                }
+            }
+            Format::Markdown => {
+                for record in records {
+                    writeln!(
+                        out,
+                        "- [{}]({}) — {}, {}",
+                        record.title.replace("]", "\\]"),
+                        record.url,
+                        record.site,
+                        &record.date[..10]
+                    )?;
+                    if record.synopsis.len() > 0 {
+                        writeln!(out, "  {}", record.synopsis.replace("\n", " "))?;
+                    }
+                }
+            }
+            Format::Atom => write_atom(out, records)?,
+        }
+        Ok(())
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+pub fn escape_xml(text: &str) -> String {
+    text.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+        .replace("'", "&apos;")
+}
+
+pub fn unescape_xml(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn write_atom(out: &mut dyn Write, records: &[ChangeRecord]) -> Result<(), Error> {
+    let updated = records
+        .iter()
+        .map(|r| r.date.clone())
+        .max()
+        .unwrap_or_else(|| iso_date(&Utc::now().naive_utc()));
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"utf-8\"?>")?;
+    writeln!(out, "<feed xmlns=\"http://www.w3.org/2005/Atom\">")?;
+    writeln!(out, "  <id>urn:wiki-rust:recent-changes</id>")?;
+    writeln!(out, "  <title>Recent Changes</title>")?;
+    writeln!(out, "  <updated>{}</updated>", updated)?;
+    writeln!(out, "  <generator>wiki-changes</generator>")?;
+    for record in records {
+        writeln!(out, "  <entry>")?;
+        writeln!(
+            out,
+            "    <id>{}#{}</id>",
+            escape_xml(&record.url),
+            escape_xml(&record.date)
+        )?;
+        writeln!(out, "    <title>{}</title>", escape_xml(&record.title))?;
+        writeln!(out, "    <updated>{}</updated>", record.date)?;
+        writeln!(
+            out,
+            "    <author><name>{}</name></author>",
+            escape_xml(&record.site)
+        )?;
+        writeln!(
+            out,
+            "    <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>",
+            escape_xml(&record.url)
+        )?;
+        writeln!(
+            out,
+            "    <summary>{}</summary>",
+            escape_xml(&record.synopsis)
+        )?;
+        writeln!(out, "  </entry>")?;
+    }
+    writeln!(out, "</feed>")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(title: &str, synopsis: &str) -> ChangeRecord {
+        ChangeRecord {
+            site: "fed.example".to_owned(),
+            slug: "a-page".to_owned(),
+            title: title.to_owned(),
+            date: "2020-01-31T08:30:00Z".to_owned(),
+            synopsis: synopsis.to_owned(),
+            url: "http://fed.example/a-page.html".to_owned(),
+        }
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("plain text"), "plain text");
+        assert_eq!(csv_field("a, b"), "\"a, b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("carriage\rreturn"), "\"carriage\rreturn\"");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn csv_rows_keep_one_record_per_row() {
+        let mut out = Vec::new();
+        Format::Csv
+            .write(&mut out, &[record("A, \"B\"", "first\nsecond")])
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "site,slug,title,date,synopsis,url\n\
+             fed.example,a-page,\"A, \"\"B\"\"\",2020-01-31T08:30:00Z,\"first\nsecond\",\
+             http://fed.example/a-page.html\n"
+        );
+    }
+
+    #[test]
+    fn xml_escaping_round_trips() {
+        let text = "<a href=\"x\">Tom & Jerry's</a>";
+        let escaped = escape_xml(text);
+        assert_eq!(
+            escaped,
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+        assert_eq!(unescape_xml(&escaped), text);
+        assert_eq!(unescape_xml("&amp;lt;"), "&lt;");
+    }
+
+    #[test]
+    fn atom_escapes_titles() {
+        let mut out = Vec::new();
+        Format::Atom
+            .write(&mut out, &[record("Tom & Jerry <3", "")])
+            .unwrap();
+        let atom = String::from_utf8(out).unwrap();
+        assert!(atom.contains("Tom &amp; Jerry &lt;3"));
+        assert!(!atom.contains("Tom & Jerry"));
+    }
+}
@@ -0,0 +1,280 @@
+use crate::Page;
+use chrono::NaiveDateTime;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+const DIFF_CONTEXT: usize = 3;
+const DIFF_MAX_WORDS: usize = 400;
+
+/// What happened to a page within a time window, built from its journal.
+pub struct JournalSummary {
+    pub actions: BTreeMap<String, usize>,
+    pub forked_from: Vec<String>,
+    pub diffs: Vec<String>,
+}
+
+impl JournalSummary {
+    /// Replays the journal, summarizing the actions dated within the window.
+    /// Edited paragraphs are compared with their text before the edit.
+    pub fn from_page(
+        page: &Page,
+        since: Option<NaiveDateTime>,
+        until: Option<NaiveDateTime>,
+    ) -> JournalSummary {
+        let mut summary = JournalSummary {
+            actions: BTreeMap::new(),
+            forked_from: Vec::new(),
+            diffs: Vec::new(),
+        };
+        let mut texts: HashMap<String, String> = HashMap::new();
+        for change in &page.journal {
+            let in_window = since.map(|s| change.date >= s).unwrap_or(true)
+                && until.map(|u| change.date <= u).unwrap_or(true);
+            if in_window {
+                *summary.actions.entry(change.r#type.clone()).or_insert(0) += 1;
+            }
+            match change.r#type.as_str() {
+                "create" => {
+                    for item in change.created_story() {
+                        texts.insert(item.id, item.text.unwrap_or_default());
+                    }
+                }
+                "fork" => {
+                    if let Some(site) = &change.site {
+                        if in_window && !summary.forked_from.contains(site) {
+                            summary.forked_from.push(site.clone());
+                        }
+                    }
+                }
+                "add" | "edit" => {
+                    if let Some(item) = change.story_item() {
+                        let text = item.text.unwrap_or_default();
+                        if in_window && change.r#type == "edit" {
+                            if let Some(before) = texts.get(&item.id) {
+                                if let Some(diff) = word_diff(before, &text) {
+                                    summary.diffs.push(diff);
+                                }
+                            }
+                        }
+                        texts.insert(item.id, text);
+                    }
+                }
+                "remove" => {
+                    if let Some(id) = &change.id {
+                        texts.remove(id);
+                    }
+                }
+                _ => {}
+            }
+        }
+        summary
+    }
+}
+
+impl fmt::Display for JournalSummary {
+    /// e.g. "2 edits, 1 add, forked from x.example"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts: Vec<String> = self
+            .actions
+            .iter()
+            .filter(|(action, _)| *action != "fork" || self.forked_from.len() == 0)
+            .map(|(action, count)| {
+                format!("{} {}{}", count, action, if *count == 1 { "" } else { "s" })
+            })
+            .collect();
+        for site in &self.forked_from {
+            parts.push(format!("forked from {}", site));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+enum Op<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Where a page came from and how much it has changed, over its whole journal.
+pub struct PageHistory {
+    pub created: Option<NaiveDateTime>,
+    /// Actions that changed the story after the page was created.
+    pub edits: usize,
+    /// Sites named by journal actions, which record where forked pages
+    /// and items dragged from other pages came from.
+    pub forked_from: Vec<String>,
+    pub last_change: Option<NaiveDateTime>,
+}
+
+impl PageHistory {
+    /// Pages created before journals were kept date from their first action.
+    pub fn from_page(page: &Page) -> PageHistory {
+        let mut forked_from: Vec<String> = Vec::new();
+        for change in &page.journal {
+            if let Some(site) = &change.site {
+                if !forked_from.contains(site) {
+                    forked_from.push(site.clone());
+                }
+            }
+        }
+        PageHistory {
+            created: page
+                .journal
+                .iter()
+                .find(|change| change.r#type == "create")
+                .or_else(|| page.journal.first())
+                .map(|change| change.date),
+            edits: page
+                .journal
+                .iter()
+                .filter(|change| {
+                    ["add", "edit", "remove", "move"].contains(&change.r#type.as_str())
+                })
+                .count(),
+            forked_from,
+            last_change: page.journal.iter().map(|change| change.date).max(),
+        }
+    }
+}
+
+/// A short inline word diff, e.g. "... the [-quick-]{+slow+} fox ...".
+/// Returns None when the texts are the same.
+pub fn word_diff(before: &str, after: &str) -> Option<String> {
+    if before == after {
+        return None;
+    }
+    let old: Vec<&str> = before.split_whitespace().collect();
+    let new: Vec<&str> = after.split_whitespace().collect();
+    if old.len() > DIFF_MAX_WORDS || new.len() > DIFF_MAX_WORDS {
+        return Some(format!(
+            "rewritten ({} words to {} words)",
+            old.len(),
+            new.len()
+        ));
+    }
+    // Longest common subsequence table, filled from the end.
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push(Op::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            ops.push(Op::Added(new[j]));
+            j += 1;
+        } else {
+            ops.push(Op::Removed(old[i]));
+            i += 1;
+        }
+    }
+    let changed: Vec<bool> = ops.iter().map(|op| !matches!(op, Op::Same(_))).collect();
+    if !changed.contains(&true) {
+        // Only whitespace differs.
+        return None;
+    }
+    let near_change = |index: usize| {
+        let start = index.saturating_sub(DIFF_CONTEXT);
+        let end = (index + DIFF_CONTEXT + 1).min(ops.len());
+        changed[start..end].contains(&true)
+    };
+    let mut words = Vec::new();
+    let mut elided = false;
+    let mut index = 0;
+    while index < ops.len() {
+        match &ops[index] {
+            Op::Same(word) => {
+                if near_change(index) {
+                    words.push(word.to_string());
+                    elided = false;
+                } else if !elided {
+                    words.push("...".to_owned());
+                    elided = true;
+                }
+                index += 1;
+            }
+            _ => {
+                let mut removed = Vec::new();
+                let mut added = Vec::new();
+                while index < ops.len() {
+                    match &ops[index] {
+                        Op::Removed(word) => removed.push(*word),
+                        Op::Added(word) => added.push(*word),
+                        Op::Same(_) => break,
+                    }
+                    index += 1;
+                }
+                if removed.len() > 0 {
+                    words.push(format!("[-{}-]", removed.join(" ")));
+                }
+                if added.len() > 0 {
+                    words.push(format!("{{+{}+}}", added.join(" ")));
+                }
+                elided = false;
+            }
+        }
+    }
+    Some(words.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_diff_of_equal_texts() {
+        assert_eq!(word_diff("the quick fox", "the quick fox"), None);
+        assert_eq!(word_diff("the quick  fox", "the quick\nfox "), None);
+    }
+
+    #[test]
+    fn word_diff_replacement() {
+        assert_eq!(
+            word_diff("the quick brown fox", "the slow brown fox").unwrap(),
+            "the [-quick-] {+slow+} brown fox"
+        );
+    }
+
+    #[test]
+    fn word_diff_insertion_and_removal() {
+        assert_eq!(word_diff("a b c", "a x b c").unwrap(), "a {+x+} b c");
+        assert_eq!(word_diff("a b c", "a c").unwrap(), "a [-b-] c");
+        assert_eq!(word_diff("", "new text").unwrap(), "{+new text+}");
+        assert_eq!(word_diff("old text", "").unwrap(), "[-old text-]");
+    }
+
+    #[test]
+    fn word_diff_elides_distant_context() {
+        let before = "one two three four five six seven eight nine ten";
+        let after = "one two three four five six seven eight nine TEN";
+        assert_eq!(
+            word_diff(before, after).unwrap(),
+            "... seven eight nine [-ten-] {+TEN+}"
+        );
+        let before = "one two three four five six seven eight nine ten eleven twelve";
+        let after = "ONE two three four five six seven eight nine ten eleven TWELVE";
+        assert_eq!(
+            word_diff(before, after).unwrap(),
+            "[-one-] {+ONE+} two three four ... nine ten eleven [-twelve-] {+TWELVE+}"
+        );
+    }
+
+    #[test]
+    fn word_diff_of_long_texts() {
+        let before = vec!["word"; DIFF_MAX_WORDS + 1].join(" ");
+        assert_eq!(
+            word_diff(&before, "two words").unwrap(),
+            format!("rewritten ({} words to 2 words)", DIFF_MAX_WORDS + 1)
+        );
+    }
+}
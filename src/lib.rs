@@ -1,10 +1,27 @@
-use chrono::NaiveDateTime;
-use failure::Error;
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use failure::{err_msg, Error};
 use reqwest;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 use std::time;
 use url::Url;
 
+pub mod assets;
+pub mod bundle;
+pub mod config;
+pub mod epub;
+pub mod filter;
+pub mod format;
+pub mod journal;
+pub mod links;
+pub mod markdown;
+pub mod pdf;
+pub mod render;
+pub mod sites;
+pub mod state;
+pub mod stats;
+
 fn de_from_u64<'de, D>(deserializer: D) -> Result<NaiveDateTime, D::Error>
 where
     D: Deserializer<'de>,
@@ -16,6 +33,39 @@ where
     ))
 }
 
+fn ser_to_u64<S>(date: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_u64(date.timestamp_millis() as u64)
+}
+
+/// A random 16 digit hex id, like those the wiki client gives story items.
+pub fn random_id() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos(),
+    );
+    format!("{:016x}", hasher.finish())
+}
+
+/// Whether the site contains any of the filters. An empty filter matches all sites.
+pub fn matches_filter(site: &str, site_filter: &[&str]) -> bool {
+    site_filter.len() == 0 || site_filter.iter().any(|filter| site.contains(filter))
+}
+
+/// Prefixes a bare host name with http:// so it can be parsed as a url.
+pub fn site_url(site: &str) -> String {
+    if site.contains("://") {
+        site.to_owned()
+    } else {
+        format!("http://{}", site)
+    }
+}
+
 #[derive(Deserialize)]
 pub struct Entry {
     pub slug: String,
@@ -27,22 +77,33 @@ pub struct Entry {
 
 pub struct Sitemap {
     pub name: String,
+    pub url: String,
     pub entries: Vec<Entry>,
 }
 
 impl Sitemap {
     pub fn from_url(url: &str) -> Result<Sitemap, Error> {
         let parsed_url = Url::parse(&url).unwrap().join("/system/sitemap.json")?;
-        println!("Parsing sitemap: {}", &parsed_url);
+        eprintln!("Parsing sitemap: {}", &parsed_url);
         let mut response = reqwest::get(parsed_url.as_str())?;
         let mut entries: Vec<Entry> = response.json()?;
         entries.sort_unstable_by_key(|e| e.date);
         entries.reverse();
         Ok(Sitemap {
             name: parsed_url.host_str().unwrap().to_owned(),
+            url: parsed_url.origin().ascii_serialization(),
             entries,
         })
     }
+
+    pub fn page(&self, slug: &str) -> Result<Page, Error> {
+        Page::from_site_slug(&self.url, slug)
+    }
+
+    /// The host and any port of the site, as references name it.
+    pub fn host(&self) -> &str {
+        self.url.splitn(2, "://").last().unwrap()
+    }
 }
 
 pub struct Neighborhood {
@@ -54,27 +115,255 @@ impl Neighborhood {
         Neighborhood { sites: Vec::new() }
     }
 
+    /// Adds the site unless it is already part of the neighborhood.
     pub fn add(&mut self, url: &str) -> Result<&mut Self, Error> {
+        let origin = Url::parse(&url)?.origin().ascii_serialization();
+        if self.sites.iter().any(|site| site.url == origin) {
+            return Ok(self);
+        }
         self.sites.push(Sitemap::from_url(&url)?);
         Ok(self)
     }
+
+    /// Adds every site listed in the roster items of the given page.
+    /// When filters are supplied, only sites containing one of them are added.
+    pub fn add_roster(
+        &mut self,
+        roster_site: &str,
+        slug: &str,
+        site_filter: &[&str],
+    ) -> Result<&mut Self, Error> {
+        let page = Page::from_site_slug(roster_site, slug)?;
+        for site in page.roster_sites() {
+            if !matches_filter(&site, site_filter) {
+                continue;
+            }
+            self.add(format!("http://{}", site).as_str())?;
+        }
+        Ok(self)
+    }
+
+    /// Iterates over the entries of every site, newest first.
+    pub fn changes(&self) -> RecentChanges<'_> {
+        RecentChanges {
+            sites: &self.sites,
+            positions: vec![0; self.sites.len()],
+        }
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Copy)]
+pub struct RecentChange<'a> {
+    pub site: &'a Sitemap,
+    pub entry: &'a Entry,
+}
+
+/// Merges the already sorted sitemaps of a neighborhood into one timeline.
+pub struct RecentChanges<'a> {
+    sites: &'a [Sitemap],
+    positions: Vec<usize>,
+}
+
+impl<'a> Iterator for RecentChanges<'a> {
+    type Item = RecentChange<'a>;
+
+    fn next(&mut self) -> Option<RecentChange<'a>> {
+        let mut newest: Option<(usize, &'a Entry)> = None;
+        for (index, site) in self.sites.iter().enumerate() {
+            if let Some(entry) = site.entries.get(self.positions[index]) {
+                match newest {
+                    Some((_, current)) if current.date >= entry.date => {}
+                    _ => newest = Some((index, entry)),
+                }
+            }
+        }
+        let (index, entry) = newest?;
+        self.positions[index] += 1;
+        Some(RecentChange {
+            site: &self.sites[index],
+            entry,
+        })
+    }
+}
+
+/// Describes how long ago the date was, e.g. "3 days ago".
+pub fn relative_time(date: &NaiveDateTime) -> String {
+    let elapsed = Utc::now().naive_utc() - *date;
+    let (count, unit) = if elapsed.num_minutes() < 1 {
+        return "just now".to_owned();
+    } else if elapsed.num_hours() < 1 {
+        (elapsed.num_minutes(), "minute")
+    } else if elapsed.num_days() < 1 {
+        (elapsed.num_hours(), "hour")
+    } else if elapsed.num_weeks() < 2 {
+        (elapsed.num_days(), "day")
+    } else if elapsed.num_days() < 60 {
+        (elapsed.num_weeks(), "week")
+    } else if elapsed.num_days() < 365 {
+        (elapsed.num_days() / 30, "month")
+    } else {
+        (elapsed.num_days() / 365, "year")
+    };
+    format!(
+        "{} {}{} ago",
+        count,
+        unit,
+        if count == 1 { "" } else { "s" }
+    )
+}
+
+/// Parses an ISO date or datetime, or a relative time such as "2w" or "3d".
+/// Relative times count back from now in hours, days, weeks, months or years.
+/// When end_of_day is set a bare date means the last instant of that day,
+/// so that an inclusive comparison stops short of the next midnight.
+pub fn parse_time_spec(spec: &str, end_of_day: bool) -> Result<NaiveDateTime, Error> {
+    let spec = spec.trim();
+    for format in &["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(spec, format) {
+            return Ok(date);
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(spec, "%Y-%m-%d") {
+        let start = date.and_hms(0, 0, 0);
+        return Ok(if end_of_day {
+            start + Duration::days(1) - Duration::nanoseconds(1)
+        } else {
+            start
+        });
+    }
+    let invalid = || {
+        err_msg(format!(
+            "Invalid time: {} (expected a date like 2020-01-31 or a relative time like 2w)",
+            spec
+        ))
+    };
+    let split = spec
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let count = spec[..split].parse::<i64>().map_err(|_| invalid())?;
+    let duration = match &spec[split..] {
+        "h" => Duration::hours(count),
+        "d" => Duration::days(count),
+        "w" => Duration::weeks(count),
+        "m" => Duration::days(count * 30),
+        "y" => Duration::days(count * 365),
+        _ => return Err(invalid()),
+    };
+    Ok(Utc::now().naive_utc() - duration)
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct Item {
     pub r#type: String,
     pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
 }
 
-#[derive(Deserialize)]
+impl Item {
+    pub fn new(r#type: &str, text: &str) -> Item {
+        Item {
+            r#type: r#type.to_owned(),
+            id: random_id(),
+            text: Some(text.to_owned()),
+            site: None,
+            slug: None,
+            title: None,
+            url: None,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        self.text.as_ref().map(|t| t.as_str()).unwrap_or("")
+    }
+
+    /// The url of the site a reference item points to, given the url of the site
+    /// it is on. References name their site by host, which may already carry a scheme.
+    pub fn reference_site(&self, site: &str) -> String {
+        match &self.site {
+            Some(target) => site_url(target).trim_end_matches('/').to_owned(),
+            None => site.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// The video a video item shows, when its player is one the wiki knows.
+    pub fn video(&self) -> Option<Video> {
+        let mut lines = self.text().lines();
+        let mut source = lines.next().unwrap_or("").split_whitespace();
+        let player = source.next().unwrap_or("").to_uppercase();
+        let id = source.next().unwrap_or("");
+        let (url, thumbnail) = match player.as_str() {
+            "YOUTUBE" => (
+                format!("https://www.youtube.com/watch?v={}", id),
+                Some(format!("https://img.youtube.com/vi/{}/hqdefault.jpg", id)),
+            ),
+            "VIMEO" => (format!("https://vimeo.com/{}", id), None),
+            "ARCHIVE" => (
+                format!("https://archive.org/details/{}", id),
+                Some(format!("https://archive.org/services/img/{}", id)),
+            ),
+            _ => return None,
+        };
+        Some(Video {
+            url,
+            thumbnail,
+            caption: lines.collect::<Vec<&str>>().join("\n"),
+        })
+    }
+}
+
+/// Video items name the player and id on the first line, e.g. "YOUTUBE abc123",
+/// followed by a caption.
+pub struct Video {
+    pub url: String,
+    pub thumbnail: Option<String>,
+    pub caption: String,
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct Change {
     pub r#type: String,
-    #[serde(deserialize_with = "de_from_u64")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(deserialize_with = "de_from_u64", serialize_with = "ser_to_u64")]
     pub date: NaiveDateTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item: Option<serde_json::Value>,
+    /// The id of the item an added item follows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
 }
 
-#[derive(Deserialize)]
+impl Change {
+    /// The story item carried by add and edit actions.
+    pub fn story_item(&self) -> Option<Item> {
+        match self.r#type.as_str() {
+            "add" | "edit" => serde_json::from_value(self.item.clone()?).ok(),
+            _ => None,
+        }
+    }
+
+    /// The story of the page carried by a create action.
+    pub fn created_story(&self) -> Vec<Item> {
+        self.item
+            .as_ref()
+            .and_then(|item| item.get("story"))
+            .and_then(|story| serde_json::from_value(story.clone()).ok())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct Page {
     pub title: String,
     pub story: Vec<Item>,
@@ -82,10 +371,169 @@ pub struct Page {
 }
 
 impl Page {
+    /// A new page whose journal records its creation with the given story.
+    pub fn create(title: &str, story: Vec<Item>) -> Page {
+        let item = serde_json::json!({ "title": title, "story": story });
+        Page {
+            title: title.to_owned(),
+            story,
+            journal: vec![Change {
+                r#type: "create".to_owned(),
+                id: None,
+                date: Utc::now().naive_utc(),
+                site: None,
+                item: Some(item),
+                after: None,
+            }],
+        }
+    }
+
+    /// Adds an item to the end of the story, recording it in the journal.
+    pub fn add(&mut self, item: Item, date: NaiveDateTime) {
+        self.journal.push(Change {
+            r#type: "add".to_owned(),
+            id: Some(item.id.clone()),
+            date,
+            site: None,
+            item: serde_json::to_value(&item).ok(),
+            after: self.story.last().map(|last| last.id.clone()),
+        });
+        self.story.push(item);
+    }
+
     pub fn from_site_slug(site_name: &str, slug: &str) -> Result<Page, Error> {
         let parsed_url = Url::parse(format!("{}/{}.json", &site_name, slug).as_str())?;
-        println!("Loading: {}", parsed_url);
+        eprintln!("Loading: {}", parsed_url);
         let mut response = reqwest::get(parsed_url.as_str())?;
         Ok(response.json()?)
     }
+
+    /// Site names listed in the page's roster items.
+    /// Lines with whitespace are titles or roster directives and are skipped.
+    pub fn roster_sites(&self) -> Vec<String> {
+        let mut sites = Vec::new();
+        for item in &self.story {
+            if item.r#type != "roster" {
+                continue;
+            }
+            for line in item
+                .text
+                .as_ref()
+                .map(|t| t.as_str())
+                .unwrap_or("")
+                .split("\n")
+            {
+                let line = line.trim();
+                if line.len() == 0 || line.contains(char::is_whitespace) {
+                    continue;
+                }
+                sites.push(line.to_owned());
+            }
+        }
+        sites
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(spec: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(spec, "%Y-%m-%dT%H:%M:%S%.f").unwrap()
+    }
+
+    #[test]
+    fn time_spec_dates() {
+        let start = parse_time_spec("2020-01-31", false).unwrap();
+        assert_eq!(start, date("2020-01-31T00:00:00"));
+        assert_eq!(
+            parse_time_spec(" 2020-01-31T08:30 ", true).unwrap(),
+            date("2020-01-31T08:30:00")
+        );
+        assert_eq!(
+            parse_time_spec("2020-01-31 08:30", false).unwrap(),
+            date("2020-01-31T08:30:00")
+        );
+    }
+
+    #[test]
+    fn time_spec_end_of_day_stops_before_midnight() {
+        let end = parse_time_spec("2020-01-31", true).unwrap();
+        assert!(date("2020-01-31T23:59:59.999") <= end);
+        assert!(date("2020-02-01T00:00:00") > end);
+    }
+
+    #[test]
+    fn time_spec_relative() {
+        let before = Utc::now().naive_utc();
+        let spec = parse_time_spec("2w", false).unwrap();
+        let after = Utc::now().naive_utc();
+        assert!(before - Duration::weeks(2) <= spec && spec <= after - Duration::weeks(2));
+        assert!(parse_time_spec("3d", false).unwrap() < Utc::now().naive_utc());
+    }
+
+    #[test]
+    fn time_spec_invalid() {
+        for spec in &["", "2w3d", "d", "12", "3x", "2020-13-01", "yesterday"] {
+            assert!(parse_time_spec(spec, false).is_err(), "{}", spec);
+        }
+    }
+
+    fn sitemap(name: &str, dates: &[u64]) -> Sitemap {
+        let entries = dates
+            .iter()
+            .map(|date| {
+                serde_json::from_value::<Entry>(serde_json::json!({
+                    "slug": format!("{}-{}", name, date),
+                    "title": format!("Page {}", date),
+                    "date": date,
+                    "synopsis": "",
+                }))
+                .unwrap()
+            })
+            .collect();
+        Sitemap {
+            name: name.to_owned(),
+            url: format!("http://{}", name),
+            entries,
+        }
+    }
+
+    #[test]
+    fn changes_merge_sites_newest_first() {
+        let mut neighborhood = Neighborhood::new();
+        neighborhood.sites.push(sitemap("a", &[5000, 3000, 1000]));
+        neighborhood.sites.push(sitemap("empty", &[]));
+        neighborhood.sites.push(sitemap("c", &[4000, 3000, 2000]));
+        let order: Vec<String> = neighborhood
+            .changes()
+            .map(|change| format!("{}/{}", change.site.name, change.entry.slug))
+            .collect();
+        // Changes made at the same time come in the order of their sites.
+        assert_eq!(
+            order,
+            vec!["a/a-5000", "c/c-4000", "a/a-3000", "c/c-3000", "c/c-2000", "a/a-1000"]
+        );
+        assert_eq!(Neighborhood::new().changes().count(), 0);
+    }
+
+    #[test]
+    fn videos_and_references_resolve_to_urls() {
+        let video = Item::new("video", "YOUTUBE abc123\nA talk\nabout wikis");
+        let video = video.video().unwrap();
+        assert_eq!(video.url, "https://www.youtube.com/watch?v=abc123");
+        assert_eq!(video.caption, "A talk\nabout wikis");
+        assert!(Item::new("video", "TIKTOK abc").video().is_none());
+
+        let mut reference = Item::new("reference", "");
+        assert_eq!(
+            reference.reference_site("http://a.example/"),
+            "http://a.example"
+        );
+        reference.site = Some("b.example:3000".to_owned());
+        assert_eq!(
+            reference.reference_site("http://a.example"),
+            "http://b.example:3000"
+        );
+    }
 }
@@ -0,0 +1,131 @@
+/// The slug the wiki derives from a page title, e.g. "Welcome Visitors" to "welcome-visitors".
+pub fn slug(title: &str) -> String {
+    title
+        .chars()
+        .map(|c| if c.is_whitespace() { '-' } else { c })
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Whether the text is a slug as the wiki derives them, only lowercase letters,
+/// digits and dashes. Such slugs are safe to use as file names.
+pub fn is_slug(text: &str) -> bool {
+    text.len() > 0 && slug(text) == text
+}
+
+/// A piece of item text, split on the wiki's link markup.
+#[derive(Debug, PartialEq)]
+pub enum Markup<'a> {
+    Text(&'a str),
+    /// `[[Page Title]]`
+    Internal(&'a str),
+    /// `[http://example.com label]`
+    External {
+        url: &'a str,
+        label: &'a str,
+    },
+}
+
+pub fn parse_links(text: &str) -> Vec<Markup<'_>> {
+    let mut markup = Vec::new();
+    let mut rest = text;
+    while rest.len() > 0 {
+        let internal = rest.find("[[");
+        let external = rest.find("[http");
+        let (start, is_internal) = match (internal, external) {
+            (Some(i), Some(e)) if e < i => (e, false),
+            (Some(i), _) => (i, true),
+            (None, Some(e)) => (e, false),
+            (None, None) => break,
+        };
+        let (open, close) = if is_internal { (2, "]]") } else { (1, "]") };
+        let end = match rest[start + open..].find(close) {
+            Some(end) => start + open + end,
+            None => break,
+        };
+        if start > 0 {
+            markup.push(Markup::Text(&rest[..start]));
+        }
+        let inner = &rest[start + open..end];
+        if is_internal {
+            markup.push(Markup::Internal(inner.trim()));
+        } else {
+            let mut parts = inner.splitn(2, ' ');
+            let url = parts.next().unwrap();
+            let label = parts.next().map(|l| l.trim()).unwrap_or("");
+            markup.push(Markup::External {
+                url,
+                label: if label.len() > 0 { label } else { url },
+            });
+        }
+        rest = &rest[end + close.len()..];
+    }
+    if rest.len() > 0 {
+        markup.push(Markup::Text(rest));
+    }
+    markup
+}
+
+/// Titles of the pages linked from the text with `[[Page Title]]`.
+pub fn internal_links(text: &str) -> Vec<&str> {
+    parse_links(text)
+        .into_iter()
+        .filter_map(|m| match m {
+            Markup::Internal(title) => Some(title),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs_keep_only_letters_digits_and_dashes() {
+        assert_eq!(slug("Welcome Visitors"), "welcome-visitors");
+        assert_eq!(slug("What's New? (2020)"), "whats-new-2020");
+        assert_eq!(slug("Two  Spaces\tand a Tab"), "two--spaces-and-a-tab");
+        assert_eq!(slug("Café Society"), "caf-society");
+        assert_eq!(slug("already-a-slug"), "already-a-slug");
+        assert_eq!(slug("!?"), "");
+    }
+
+    #[test]
+    fn only_derived_slugs_are_slugs() {
+        assert!(is_slug("welcome-visitors"));
+        assert!(is_slug("2020-notes"));
+        for text in &["", "../x", "/home/u/x", "Welcome", "a b", "a.b", "a\\b"] {
+            assert!(!is_slug(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn internal_links_are_trimmed_titles() {
+        let text = "see [[Foo]] and [[ Bar Baz ]], [http://x.example x] and [[Unclosed";
+        assert_eq!(internal_links(text), vec!["Foo", "Bar Baz"]);
+        assert_eq!(internal_links("no links here"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn links_split_the_text() {
+        assert_eq!(
+            parse_links("a [[B]] c [http://d.example] e [http://f.example F G]"),
+            vec![
+                Markup::Text("a "),
+                Markup::Internal("B"),
+                Markup::Text(" c "),
+                Markup::External {
+                    url: "http://d.example",
+                    label: "http://d.example"
+                },
+                Markup::Text(" e "),
+                Markup::External {
+                    url: "http://f.example",
+                    label: "F G"
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,541 @@
+use crate::assets::{mime_from_path, Asset};
+use crate::format::iso_date;
+use crate::links::{is_slug, parse_links, slug, Markup};
+use crate::{Entry, Item, Page};
+use chrono::NaiveDateTime;
+use failure::{err_msg, Error};
+use glob::{glob, Pattern};
+use regex::{Captures, Regex};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How `[[Page Title]]` links are written in Markdown.
+#[derive(Clone, Copy, PartialEq)]
+pub enum WikiLinks {
+    /// Kept as `[[Page Title]]`, as note-taking tools understand them.
+    Wiki,
+    /// A link to the page's file, `[Page Title](page-title.md)`.
+    Relative,
+}
+
+#[derive(Serialize)]
+struct FrontMatter<'a> {
+    title: &'a str,
+    slug: &'a str,
+    date: String,
+    synopsis: &'a str,
+    site: &'a str,
+}
+
+/// A page written as Markdown, with the images it had inline as separate files.
+pub struct MarkdownPage {
+    pub markdown: String,
+    /// Paths relative to the Markdown file, and their contents.
+    pub assets: Vec<(String, Asset)>,
+}
+
+/// Writes pages of a site as Markdown files named for their slugs.
+pub struct MarkdownExporter {
+    pub site: String,
+    pub links: WikiLinks,
+}
+
+impl MarkdownExporter {
+    pub fn new(site: &str, links: WikiLinks) -> MarkdownExporter {
+        MarkdownExporter {
+            site: site.trim_end_matches('/').to_owned(),
+            links,
+        }
+    }
+
+    /// Fails for slugs that are not safe to name the page's files with.
+    pub fn page(&self, entry: &Entry, page: &Page) -> Result<MarkdownPage, Error> {
+        if !is_slug(&entry.slug) {
+            return Err(err_msg(format!("Not a usable slug: {}", entry.slug)));
+        }
+        let front_matter = serde_yaml::to_string(&FrontMatter {
+            title: &page.title,
+            slug: &entry.slug,
+            date: iso_date(&entry.date),
+            synopsis: &entry.synopsis,
+            site: &self.site,
+        })?;
+        let mut markdown = format!("---\n{}---\n", front_matter.trim_start_matches("---\n"));
+        let mut assets = Vec::new();
+        for item in &page.story {
+            markdown.push('\n');
+            markdown.push_str(&self.item(&entry.slug, item, &mut assets));
+            markdown.push('\n');
+        }
+        Ok(MarkdownPage { markdown, assets })
+    }
+
+    fn item(&self, slug: &str, item: &Item, assets: &mut Vec<(String, Asset)>) -> String {
+        let text = item.text();
+        match item.r#type.as_str() {
+            "paragraph" | "markdown" | "html" => self.links(text),
+            "image" => self.image(slug, item, text, assets),
+            "code" => fence("", text),
+            "reference" => self.reference(item, text),
+            "video" => self.video(item, text),
+            "pagefold" if text.len() > 0 => format!("---\n\n*{}*", text),
+            "pagefold" => "---".to_owned(),
+            _ => fence(&item.r#type, text),
+        }
+    }
+
+    /// Converts the wiki's link markup to Markdown links.
+    pub fn links(&self, text: &str) -> String {
+        let mut markdown = String::new();
+        for markup in parse_links(text) {
+            match markup {
+                Markup::Text(text) => markdown.push_str(text),
+                Markup::Internal(title) => markdown.push_str(&match self.links {
+                    WikiLinks::Wiki => format!("[[{}]]", title),
+                    WikiLinks::Relative => format!("[{}]({}.md)", title, slug(title)),
+                }),
+                Markup::External { url, label } => {
+                    markdown.push_str(&format!("[{}]({})", label, url))
+                }
+            }
+        }
+        markdown
+    }
+
+    /// Images the wiki stores inline become files next to the page.
+    fn image(
+        &self,
+        slug: &str,
+        item: &Item,
+        caption: &str,
+        assets: &mut Vec<(String, Asset)>,
+    ) -> String {
+        let url = item.url.as_ref().map(|u| u.as_str()).unwrap_or("");
+        let source = if url.starts_with("data:") {
+            match Asset::from_data_url(url) {
+                Ok(asset) => {
+                    // Item ids come from the site too, so odd ones are numbered instead.
+                    let name = if is_slug(&item.id) {
+                        format!("{}-{}", slug, item.id)
+                    } else {
+                        format!("{}-{}", slug, assets.len() + 1)
+                    };
+                    let path = format!("assets/{}.{}", name, asset.extension());
+                    assets.push((path.clone(), asset));
+                    path
+                }
+                Err(error) => {
+                    eprintln!("Unable to decode image on {}: {}", slug, error);
+                    String::new()
+                }
+            }
+        } else if url.starts_with('/') {
+            format!("{}{}", self.site, url)
+        } else {
+            url.to_owned()
+        };
+        let alt = caption.replace('[', "").replace(']', "");
+        let image = format!("![{}]({})", alt.trim(), source);
+        if caption.len() > 0 {
+            format!("{}\n\n{}", image, self.links(caption))
+        } else {
+            image
+        }
+    }
+
+    fn reference(&self, item: &Item, synopsis: &str) -> String {
+        let slug = item.slug.as_ref().map(|s| s.as_str()).unwrap_or("");
+        let title = item.title.as_ref().map(|t| t.as_str()).unwrap_or(slug);
+        let link = match &item.site {
+            Some(site) => format!(
+                "[{}]({}/{}.html) ({})",
+                title,
+                item.reference_site(&self.site),
+                slug,
+                site
+            ),
+            None => self.links(&format!("[[{}]]", title)),
+        };
+        let mut quote = format!("> {}", link);
+        for line in self.links(synopsis).lines() {
+            quote.push_str(&format!("\n>\n> {}", line));
+        }
+        quote
+    }
+
+    fn video(&self, item: &Item, text: &str) -> String {
+        let video = match item.video() {
+            Some(video) => video,
+            None => return fence("video", text),
+        };
+        format!("[Video]({}) {}", video.url, self.links(&video.caption))
+            .trim_end()
+            .to_owned()
+    }
+}
+
+/// A fenced code block, long enough not to be closed by backticks in the text.
+fn fence(info: &str, text: &str) -> String {
+    let mut fence = "```".to_owned();
+    while text.contains(&fence) {
+        fence.push('`');
+    }
+    format!(
+        "{}{}\n{}\n{}",
+        fence,
+        info,
+        text.trim_end_matches('\n'),
+        fence
+    )
+}
+
+/// Reads Markdown notes as wiki pages. Local images are looked for next to the
+/// note, then in the folder of notes, and embedded in the page.
+pub struct MarkdownImporter<'a> {
+    pub root: &'a Path,
+    /// Page titles by the lowercased file name of their note, which links to
+    /// notes use. Add every note with add_note before converting any of them.
+    pub titles: HashMap<String, String>,
+}
+
+impl<'a> MarkdownImporter<'a> {
+    pub fn new(root: &'a Path) -> MarkdownImporter<'a> {
+        MarkdownImporter {
+            root,
+            titles: HashMap::new(),
+        }
+    }
+
+    /// Records the title of a note, so that links to it name its page.
+    pub fn add_note(&mut self, path: &Path, markdown: &str) {
+        if let Some(stem) = path.file_stem() {
+            let (title, _) = self.title_and_body(path, markdown);
+            let name = stem.to_string_lossy().to_lowercase();
+            self.titles.insert(name, title);
+        }
+    }
+
+    /// The page for a note. The title comes from the front matter, a leading
+    /// heading or the file name, in that order.
+    pub fn page(&self, path: &Path, markdown: &str, date: NaiveDateTime) -> Page {
+        let (title, lines) = self.title_and_body(path, markdown);
+        let mut page = Page::create(&title, Vec::new());
+        page.journal[0].date = date;
+        for item in self.items(path, &lines) {
+            page.add(item, date);
+        }
+        page
+    }
+
+    /// The title of a note and its lines without the heading that gave the title.
+    fn title_and_body<'m>(&self, path: &Path, markdown: &'m str) -> (String, Vec<&'m str>) {
+        let (front_matter, body) = split_front_matter(markdown);
+        let mut lines: Vec<&str> = body.lines().collect();
+        while lines.first().map(|l| l.trim().len() == 0) == Some(true) {
+            lines.remove(0);
+        }
+        let heading = lines
+            .first()
+            .filter(|line| line.starts_with("# "))
+            .map(|line| line[2..].trim().to_owned());
+        let title = front_matter
+            .as_ref()
+            .and_then(|matter| matter.get("title"))
+            .and_then(|title| title.as_str())
+            .map(|title| title.to_owned())
+            .or_else(|| heading.clone())
+            .unwrap_or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+        if heading.as_ref() == Some(&title) {
+            lines.remove(0);
+        }
+        (title, lines)
+    }
+
+    /// Headings and lists become markdown items, fenced code becomes code items,
+    /// images on lines of their own become image items and the rest are paragraphs.
+    fn items(&self, path: &Path, lines: &[&str]) -> Vec<Item> {
+        let image = Regex::new(
+            r"^!\[([^\]]*)\]\(([^)\s]+)(?:\s+[^)]*)?\)$|^!\[\[([^\]|]+)(?:\|[^\]]*)?\]\]$",
+        )
+        .unwrap();
+        let rule = Regex::new(r"^(\*\s*){3,}$|^(-\s*){3,}$|^(_\s*){3,}$").unwrap();
+        let block = Regex::new(r"^\s*([-*+]|\d+\.|>|\|)").unwrap();
+        let mut items = Vec::new();
+        let mut paragraph: Vec<&str> = Vec::new();
+        let mut fence: Option<(String, Vec<&str>)> = None;
+        for &line in lines {
+            if let Some((marker, code)) = fence.as_mut() {
+                if line.trim_start().starts_with(marker.as_str()) {
+                    items.push(Item::new("code", &code.join("\n")));
+                    fence = None;
+                } else {
+                    code.push(line);
+                }
+                continue;
+            }
+            let trimmed = line.trim();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                self.flush(&mut items, &mut paragraph);
+                let marker: String = trimmed
+                    .chars()
+                    .take_while(|&c| c == trimmed.chars().next().unwrap())
+                    .collect();
+                fence = Some((marker, Vec::new()));
+            } else if trimmed.len() == 0 {
+                self.flush(&mut items, &mut paragraph);
+            } else if trimmed.starts_with('#') {
+                self.flush(&mut items, &mut paragraph);
+                items.push(Item::new("markdown", &wiki_links(trimmed, &self.titles)));
+            } else if rule.is_match(trimmed) && paragraph.len() == 0 {
+                items.push(Item::new("pagefold", ""));
+            } else if let Some(captures) = image.captures(trimmed) {
+                self.flush(&mut items, &mut paragraph);
+                let (caption, source) = match captures.get(3) {
+                    Some(file) => ("", file.as_str()),
+                    None => (&captures[1], &captures[2]),
+                };
+                items.push(self.image(path, caption, source));
+            } else {
+                paragraph.push(line);
+            }
+        }
+        if let Some((_, code)) = fence {
+            items.push(Item::new("code", &code.join("\n")));
+        }
+        self.flush(&mut items, &mut paragraph);
+        // Paragraphs holding lists, quotes or tables keep their Markdown.
+        for item in &mut items {
+            let text = item.text();
+            if item.r#type == "paragraph" && text.lines().any(|l| block.is_match(l)) {
+                item.r#type = "markdown".to_owned();
+            } else if item.r#type == "paragraph" {
+                item.text = Some(text.lines().map(|l| l.trim()).collect::<Vec<_>>().join(" "));
+            }
+        }
+        items
+    }
+
+    fn image(&self, path: &Path, caption: &str, source: &str) -> Item {
+        let mut item = Item::new("image", caption);
+        if source.contains("://") || source.starts_with("data:") {
+            item.url = Some(source.to_owned());
+            return item;
+        }
+        let source = percent_decode(source);
+        match self.find(path, &source).map(|file| fs::read(&file)) {
+            Some(Ok(data)) => {
+                let asset = Asset {
+                    mime: mime_from_path(&source).to_owned(),
+                    data,
+                };
+                item.url = Some(asset.data_url());
+                item
+            }
+            Some(Err(error)) => {
+                eprintln!("Unable to read image {}: {}", source, error);
+                Item::new("paragraph", &format!("Missing image: {}", source))
+            }
+            None => {
+                eprintln!("Unable to find image {} for {}", source, path.display());
+                Item::new("paragraph", &format!("Missing image: {}", source))
+            }
+        }
+    }
+
+    fn flush(&self, items: &mut Vec<Item>, paragraph: &mut Vec<&str>) {
+        if paragraph.len() > 0 {
+            let text = wiki_links(&paragraph.join("\n"), &self.titles);
+            items.push(Item::new("paragraph", &text));
+            paragraph.clear();
+        }
+    }
+
+    /// Note-taking tools link files by name alone, wherever they are in the folder.
+    fn find(&self, path: &Path, source: &str) -> Option<PathBuf> {
+        let near = path.parent().unwrap_or(self.root).join(source);
+        if near.exists() {
+            return Some(near);
+        }
+        if self.root.join(source).exists() {
+            return Some(self.root.join(source));
+        }
+        let name = Path::new(source).file_name()?.to_str()?;
+        let pattern = self.root.join("**").join(Pattern::escape(name));
+        glob(pattern.to_str()?)
+            .ok()?
+            .filter_map(|found| found.ok())
+            .next()
+    }
+}
+
+/// Front matter between `---` lines at the start of a note, and the rest of it.
+fn split_front_matter(markdown: &str) -> (Option<serde_yaml::Value>, &str) {
+    if !markdown.starts_with("---") {
+        return (None, markdown);
+    }
+    let rest = &markdown[3..];
+    match rest.find("\n---") {
+        Some(end) => {
+            let body = rest[end + 4..].splitn(2, '\n').nth(1).unwrap_or("");
+            (serde_yaml::from_str(&rest[..end]).ok(), body)
+        }
+        None => (None, markdown),
+    }
+}
+
+/// Converts Markdown links to the wiki's link markup. Links to other notes become
+/// `[[Page Title]]`, with the title of the note's page when it is among the titles,
+/// which are keyed by lowercased file name. Aliases and headings are dropped from
+/// wiki links, which the wiki does not support.
+pub fn wiki_links(text: &str, titles: &HashMap<String, String>) -> String {
+    let wiki = Regex::new(r"\[\[([^\]|#]*)(?:#[^\]|]*)?(?:\|[^\]]*)?\]\]").unwrap();
+    let link = Regex::new(r"\[([^\]]*)\]\(([^)\s]+)\)").unwrap();
+    let title = |name: &str| {
+        let name = name.trim();
+        titles
+            .get(&name.to_lowercase())
+            .map(|title| title.as_str())
+            .unwrap_or(name)
+            .to_owned()
+    };
+    let text = wiki.replace_all(text, |captures: &Captures| {
+        format!("[[{}]]", title(&captures[1]))
+    });
+    link.replace_all(&text, |captures: &Captures| {
+        let (label, target) = (&captures[1], &captures[2]);
+        if target.contains("://") {
+            format!("[{} {}]", target, label)
+        } else if target.ends_with(".md") {
+            let name = percent_decode(target.trim_end_matches(".md"));
+            format!("[[{}]]", title(name.rsplit('/').next().unwrap()))
+        } else {
+            captures[0].to_owned()
+        }
+    })
+    .into_owned()
+}
+
+/// Decodes the %20 style escapes note-taking tools use in link targets.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escape {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn front_matter_is_split_from_the_note() {
+        let (matter, body) = split_front_matter("---\ntitle: Notes\n---\n# Heading\ntext\n");
+        assert_eq!(
+            matter.unwrap().get("title").unwrap().as_str(),
+            Some("Notes")
+        );
+        assert_eq!(body, "# Heading\ntext\n");
+        let (matter, body) = split_front_matter("--- not front matter\ntext");
+        assert!(matter.is_none());
+        assert_eq!(body, "--- not front matter\ntext");
+    }
+
+    #[test]
+    fn links_name_the_pages_of_notes() {
+        let mut titles = HashMap::new();
+        titles.insert("daily log".to_owned(), "What Happened Today".to_owned());
+        assert_eq!(
+            wiki_links("See [[Daily Log#Monday|the log]] and [[Other]].", &titles),
+            "See [[What Happened Today]] and [[Other]]."
+        );
+        assert_eq!(
+            wiki_links(
+                "[log](notes/Daily%20Log.md) [site](https://fed.example)",
+                &titles
+            ),
+            "[[What Happened Today]] [https://fed.example site]"
+        );
+        assert_eq!(
+            wiki_links("[image](picture.png)", &titles),
+            "[image](picture.png)"
+        );
+    }
+
+    #[test]
+    fn percent_escapes_are_decoded() {
+        assert_eq!(percent_decode("Daily%20Log"), "Daily Log");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn notes_become_items() {
+        let importer = MarkdownImporter::new(Path::new("."));
+        let lines = vec![
+            "## Section",
+            "",
+            "First line",
+            "  continued.",
+            "",
+            "- one",
+            "- two",
+            "",
+            "---",
+            "```rust",
+            "fn main() {}",
+            "```",
+            "![photo](https://fed.example/photo.jpg)",
+        ];
+        let items: Vec<(String, String)> = importer
+            .items(Path::new("note.md"), &lines)
+            .into_iter()
+            .map(|item| (item.r#type, item.text.unwrap_or_default()))
+            .collect();
+        let expected = vec![
+            ("markdown", "## Section"),
+            ("paragraph", "First line continued."),
+            ("markdown", "- one\n- two"),
+            ("pagefold", ""),
+            ("code", "fn main() {}"),
+            ("image", "photo"),
+        ];
+        let expected: Vec<(String, String)> = expected
+            .into_iter()
+            .map(|(t, text)| (t.to_owned(), text.to_owned()))
+            .collect();
+        assert_eq!(items, expected);
+    }
+
+    #[test]
+    fn titles_come_from_front_matter_heading_or_file_name() {
+        let mut importer = MarkdownImporter::new(Path::new("."));
+        importer.add_note(Path::new("a.md"), "---\ntitle: Front\n---\n# Heading\n");
+        importer.add_note(Path::new("B.md"), "\n# Heading\ntext\n");
+        importer.add_note(Path::new("c.md"), "text\n");
+        assert_eq!(importer.titles["a"], "Front");
+        assert_eq!(importer.titles["b"], "Heading");
+        assert_eq!(importer.titles["c"], "c");
+    }
+}
@@ -0,0 +1,1148 @@
+use crate::assets::Asset;
+use crate::format::unescape_xml as unescape;
+use crate::journal::PageHistory;
+use crate::links::{parse_links, slug, Markup};
+use crate::render::Renderer;
+use crate::{Entry, Item, Page};
+use chrono::NaiveDateTime;
+use failure::Error;
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+// A4, in points.
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 56.0;
+const BODY_SIZE: f32 = 11.0;
+const CODE_SIZE: f32 = 9.0;
+
+/// HTML elements whose text starts on a line of its own.
+#[rustfmt::skip]
+const BLOCK_TAGS: &[&str] = &[
+    "p", "div", "li", "h1", "h2", "h3", "h4", "h5", "h6", "tr", "br", "blockquote", "pre",
+];
+
+/// Widths of the printable ASCII characters in thousandths of the font size,
+/// from the metrics of the standard Helvetica fonts.
+#[rustfmt::skip]
+const HELVETICA: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+#[rustfmt::skip]
+const HELVETICA_BOLD: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+
+/// The standard fonts every PDF reader provides, so none need embedding.
+#[derive(Clone, Copy, PartialEq)]
+enum Font {
+    Regular,
+    Bold,
+    Italic,
+    Mono,
+}
+
+const FONTS: [Font; 4] = [Font::Regular, Font::Bold, Font::Italic, Font::Mono];
+
+impl Font {
+    fn resource(self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+            Font::Italic => "F3",
+            Font::Mono => "F4",
+        }
+    }
+
+    fn base_font(self) -> &'static str {
+        match self {
+            Font::Regular => "Helvetica",
+            Font::Bold => "Helvetica-Bold",
+            Font::Italic => "Helvetica-Oblique",
+            Font::Mono => "Courier",
+        }
+    }
+
+    /// The width of WinAnsi encoded text at the given size.
+    fn width(self, text: &[u8], size: f32) -> f32 {
+        let units: u32 = text
+            .iter()
+            .map(|&byte| match (self, byte) {
+                (Font::Mono, _) => 600,
+                (Font::Bold, 32..=126) => HELVETICA_BOLD[byte as usize - 32] as u32,
+                (_, 32..=126) => HELVETICA[byte as usize - 32] as u32,
+                (_, 0x85) | (_, 0x97) => 1000,
+                (_, 0x95) => 350,
+                (_, 0x91) | (_, 0x92) => 222,
+                (_, 0x93) | (_, 0x94) => 333,
+                _ => 556,
+            })
+            .sum();
+        units as f32 * size / 1000.0
+    }
+}
+
+/// Encodes text for the standard fonts, which use WinAnsiEncoding.
+/// Characters it lacks are replaced with a question mark.
+fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' => c as u8,
+            '\u{a0}'..='\u{ff}' => c as u32 as u8,
+            '\u{2026}' => 0x85,
+            '\u{2018}' => 0x91,
+            '\u{2019}' => 0x92,
+            '\u{201c}' => 0x93,
+            '\u{201d}' => 0x94,
+            '\u{2022}' => 0x95,
+            '\u{2013}' => 0x96,
+            '\u{2014}' => 0x97,
+            '\t' => b' ',
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// A literal string, with the characters that delimit it escaped.
+fn literal(bytes: &[u8]) -> Vec<u8> {
+    let mut string = vec![b'('];
+    for &byte in bytes {
+        if byte == b'(' || byte == b')' || byte == b'\\' {
+            string.push(b'\\');
+        }
+        string.push(byte);
+    }
+    string.push(b')');
+    string
+}
+
+/// A hex string in UTF-16, which outline titles and metadata may use for any text.
+fn unicode(text: &str) -> String {
+    let mut string = String::from("<FEFF");
+    for unit in text.encode_utf16() {
+        string.push_str(&format!("{:04X}", unit));
+    }
+    string.push('>');
+    string
+}
+
+#[derive(Clone)]
+enum Target {
+    /// A page included in the document, by slug.
+    Page(String),
+    Uri(String),
+}
+
+/// The style of Markdown text, changed by the elements around it.
+#[derive(Clone)]
+struct Style {
+    font: Font,
+    size: f32,
+    target: Option<Target>,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            font: Font::Regular,
+            size: BODY_SIZE,
+            target: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Word {
+    text: Vec<u8>,
+    font: Font,
+    size: f32,
+    target: Option<Target>,
+    /// Whether the source had whitespace before the word.
+    space: bool,
+}
+
+enum Block {
+    Text {
+        words: Vec<Word>,
+        indent: f32,
+        space_before: f32,
+    },
+    /// Preformatted lines, set in the monospaced font.
+    Lines(Vec<String>),
+    Image {
+        image: usize,
+        caption: Vec<Word>,
+    },
+    Rule(String),
+}
+
+/// An image in a form PDF readers decode themselves.
+struct Image {
+    width: u32,
+    height: u32,
+    color_space: &'static str,
+    filter: &'static str,
+    parms: Option<String>,
+    data: Vec<u8>,
+}
+
+impl Image {
+    fn decode(asset: &Asset) -> Option<Image> {
+        match asset.mime.as_str() {
+            "image/jpeg" => Image::jpeg(&asset.data),
+            "image/png" => Image::png(&asset.data),
+            _ => None,
+        }
+    }
+
+    /// JPEG data is embedded as is, once the size is read from its frame header.
+    fn jpeg(data: &[u8]) -> Option<Image> {
+        let mut i = 2;
+        while i + 9 < data.len() {
+            if data[i] != 0xFF {
+                return None;
+            }
+            let marker = data[i + 1];
+            let frame = (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker);
+            if frame {
+                let color_space = match data[i + 9] {
+                    1 => "DeviceGray",
+                    3 => "DeviceRGB",
+                    _ => return None,
+                };
+                return Some(Image {
+                    height: u16::from_be_bytes([data[i + 5], data[i + 6]]) as u32,
+                    width: u16::from_be_bytes([data[i + 7], data[i + 8]]) as u32,
+                    color_space,
+                    filter: "DCTDecode",
+                    parms: None,
+                    data: data.to_vec(),
+                });
+            }
+            i += 2 + u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+        }
+        None
+    }
+
+    /// PNG image data is a zlib stream with PNG row filters, which PDF readers
+    /// undo given the right predictor. Only 8 bit gray and RGB without
+    /// interlacing or transparency can be embedded this way.
+    fn png(data: &[u8]) -> Option<Image> {
+        if !data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            return None;
+        }
+        let mut i = 8;
+        let mut header = None;
+        let mut idat = Vec::new();
+        while i + 8 <= data.len() {
+            let length =
+                u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+            let kind = &data[i + 4..i + 8];
+            let chunk = data.get(i + 8..i + 8 + length)?;
+            match kind {
+                b"IHDR" if length >= 13 => header = Some(chunk.to_vec()),
+                b"IDAT" => idat.extend_from_slice(chunk),
+                b"IEND" => break,
+                _ => {}
+            }
+            i += 12 + length;
+        }
+        let header = header?;
+        let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let (depth, color, interlace) = (header[8], header[9], header[12]);
+        let (color_space, colors) = match color {
+            0 => ("DeviceGray", 1),
+            2 => ("DeviceRGB", 3),
+            _ => return None,
+        };
+        if depth != 8 || interlace != 0 {
+            return None;
+        }
+        Some(Image {
+            width,
+            height,
+            color_space,
+            filter: "FlateDecode",
+            parms: Some(format!(
+                "<< /Predictor 15 /Colors {} /BitsPerComponent 8 /Columns {} >>",
+                colors, width
+            )),
+            data: idat,
+        })
+    }
+}
+
+struct Link {
+    rect: [f32; 4],
+    target: Target,
+}
+
+#[derive(Default)]
+struct PdfPage {
+    content: Vec<u8>,
+    links: Vec<Link>,
+}
+
+impl PdfPage {
+    fn op(&mut self, op: &str) {
+        self.content.extend_from_slice(op.as_bytes());
+        self.content.push(b'\n');
+    }
+
+    fn text(&mut self, font: Font, size: f32, x: f32, y: f32, text: &[u8]) {
+        self.op(&format!(
+            "BT /{} {} Tf {:.2} {:.2} Td",
+            font.resource(),
+            size,
+            x,
+            y
+        ));
+        self.content.extend_from_slice(&literal(text));
+        self.op(" Tj ET");
+    }
+}
+
+/// Lays out pages of a site as a PDF: each page starts on a new sheet
+/// and gets a bookmark, and links between printed pages jump to them.
+pub struct PdfDocument<'a> {
+    renderer: &'a Renderer,
+    pages: Vec<PdfPage>,
+    images: Vec<Image>,
+    /// Images already embedded, by url.
+    image_urls: HashMap<String, usize>,
+    /// Where each printed page starts, as a sheet number and height.
+    anchors: HashMap<String, (usize, f32)>,
+    outline: Vec<(String, String)>,
+    y: f32,
+}
+
+impl<'a> PdfDocument<'a> {
+    pub fn new(renderer: &'a Renderer) -> PdfDocument<'a> {
+        PdfDocument {
+            renderer,
+            pages: Vec::new(),
+            images: Vec::new(),
+            image_urls: HashMap::new(),
+            anchors: HashMap::new(),
+            outline: Vec::new(),
+            y: 0.0,
+        }
+    }
+
+    pub fn cover(&mut self, date: &NaiveDateTime) {
+        self.new_page();
+        let page = self.pages.last_mut().unwrap();
+        let name = win_ansi(self.renderer.site_name());
+        let x = (PAGE_WIDTH - Font::Bold.width(&name, 28.0)) / 2.0;
+        page.text(Font::Bold, 28.0, x.max(MARGIN), PAGE_HEIGHT * 0.6, &name);
+        let date = win_ansi(&date.format("%B %e, %Y").to_string());
+        let x = (PAGE_WIDTH - Font::Regular.width(&date, 14.0)) / 2.0;
+        page.text(Font::Regular, 14.0, x, PAGE_HEIGHT * 0.6 - 30.0, &date);
+    }
+
+    pub fn page(&mut self, slug: &str, page: &Page) {
+        self.new_page();
+        self.anchors
+            .insert(slug.to_owned(), (self.pages.len() - 1, PAGE_HEIGHT));
+        self.outline.push((page.title.clone(), slug.to_owned()));
+        let title = self.words(&page.title, Font::Bold, 20.0);
+        self.text(&title, 0.0, 0.0);
+        self.y -= 8.0;
+        for item in &page.story {
+            for block in self.item(item) {
+                self.block(block);
+            }
+        }
+    }
+
+    /// An appendix giving the provenance of each page.
+    pub fn history(&mut self, pages: &[(Entry, Page)]) {
+        self.new_page();
+        let heading = self.words("History", Font::Bold, 20.0);
+        self.text(&heading, 0.0, 0.0);
+        self.y -= 8.0;
+        let date = |date: Option<NaiveDateTime>| {
+            date.map(|d| d.format("%Y-%m-%d").to_string())
+                .unwrap_or_else(|| "unknown".to_owned())
+        };
+        for (entry, page) in pages {
+            let history = PageHistory::from_page(page);
+            let mut title = Vec::new();
+            split(
+                &mut title,
+                &page.title,
+                Font::Bold,
+                BODY_SIZE,
+                Some(self.page_target(&self.renderer.site, &entry.slug)),
+                false,
+            );
+            self.text(&title, 0.0, 6.0);
+            let mut details = format!(
+                "Created {}, {} edits, last changed {}",
+                date(history.created),
+                history.edits,
+                date(history.last_change)
+            );
+            if history.forked_from.len() > 0 {
+                details.push_str(&format!(", forked from {}", history.forked_from.join(", ")));
+            }
+            let details = self.words(&details, Font::Regular, 9.0);
+            self.text(&details, 14.0, 0.0);
+        }
+    }
+
+    fn new_page(&mut self) {
+        self.pages.push(PdfPage::default());
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    /// Starts a new sheet unless the given height still fits on this one.
+    fn ensure(&mut self, height: f32) {
+        if self.y - height < MARGIN && self.y < PAGE_HEIGHT - MARGIN {
+            self.new_page();
+        }
+    }
+
+    fn block(&mut self, block: Block) {
+        match block {
+            Block::Text {
+                words,
+                indent,
+                space_before,
+            } => self.text(&words, indent, space_before),
+            Block::Lines(lines) => {
+                self.y -= 4.0;
+                let columns = ((PAGE_WIDTH - 2.0 * MARGIN) / (0.6 * CODE_SIZE)) as usize;
+                for line in lines {
+                    let line = win_ansi(&line);
+                    let chunks: Vec<&[u8]> = if line.len() == 0 {
+                        vec![&line[..]]
+                    } else {
+                        line.chunks(columns).collect()
+                    };
+                    for chunk in chunks {
+                        self.ensure(CODE_SIZE * 1.3);
+                        self.y -= CODE_SIZE * 1.3;
+                        let y = self.y;
+                        self.pages.last_mut().unwrap().text(
+                            Font::Mono,
+                            CODE_SIZE,
+                            MARGIN,
+                            y,
+                            chunk,
+                        );
+                    }
+                }
+                self.y -= 4.0;
+            }
+            Block::Image { image, caption } => {
+                let (width, height) = {
+                    let image = &self.images[image];
+                    (image.width as f32 * 0.75, image.height as f32 * 0.75)
+                };
+                let scale = (1.0f32)
+                    .min((PAGE_WIDTH - 2.0 * MARGIN) / width)
+                    .min((PAGE_HEIGHT - 2.0 * MARGIN) / 2.0 / height);
+                let (width, height) = (width * scale, height * scale);
+                self.y -= 6.0;
+                self.ensure(height);
+                self.y -= height;
+                let y = self.y;
+                self.pages.last_mut().unwrap().op(&format!(
+                    "q {:.2} 0 0 {:.2} {:.2} {:.2} cm /Im{} Do Q",
+                    width,
+                    height,
+                    MARGIN,
+                    y,
+                    image + 1
+                ));
+                self.text(&caption, 0.0, 2.0);
+            }
+            Block::Rule(label) => {
+                self.ensure(BODY_SIZE * 3.0);
+                self.y -= BODY_SIZE;
+                let y = self.y;
+                let label = win_ansi(&label);
+                let x = (PAGE_WIDTH - Font::Regular.width(&label, 9.0)) / 2.0;
+                let page = self.pages.last_mut().unwrap();
+                page.op(&format!(
+                    "0.6 G 0.5 w {:.2} {:.2} m {:.2} {:.2} l S 0 G",
+                    MARGIN,
+                    y,
+                    PAGE_WIDTH - MARGIN,
+                    y
+                ));
+                page.text(Font::Regular, 9.0, x, y - 12.0, &label);
+                self.y -= BODY_SIZE * 2.0;
+            }
+        }
+    }
+
+    /// Fills lines with as many words as fit, recording where links are drawn.
+    fn text(&mut self, words: &[Word], indent: f32, space_before: f32) {
+        if words.len() == 0 {
+            return;
+        }
+        self.y -= space_before;
+        let left = MARGIN + indent;
+        let right = PAGE_WIDTH - MARGIN;
+        let mut lines: Vec<Vec<(f32, &Word)>> = vec![Vec::new()];
+        let mut x = left;
+        for word in words {
+            let width = word.font.width(&word.text, word.size);
+            let space = if word.space && x > left {
+                word.font.width(b" ", word.size)
+            } else {
+                0.0
+            };
+            if x + space + width > right && x > left {
+                lines.push(Vec::new());
+                x = left;
+            } else {
+                x += space;
+            }
+            lines.last_mut().unwrap().push((x, word));
+            x += width;
+        }
+        for line in lines {
+            let size = line.iter().map(|(_, w)| w.size).fold(0.0, f32::max);
+            self.ensure(size * 1.35);
+            self.y -= size * 1.35;
+            let y = self.y + size * 0.3;
+            let page = self.pages.last_mut().unwrap();
+            for (x, word) in line {
+                if word.target.is_some() {
+                    page.op("0 0 0.6 rg");
+                }
+                page.text(word.font, word.size, x, y, &word.text);
+                if let Some(target) = &word.target {
+                    page.op("0 g");
+                    page.links.push(Link {
+                        rect: [
+                            x,
+                            y - word.size * 0.25,
+                            x + word.font.width(&word.text, word.size),
+                            y + word.size,
+                        ],
+                        target: target.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Splits text into words, resolving the wiki's link markup to targets.
+    fn words(&self, text: &str, font: Font, size: f32) -> Vec<Word> {
+        let mut words = Vec::new();
+        // Links are parsed without the whitespace around them, which ends the text before.
+        let mut space = false;
+        for markup in parse_links(text) {
+            space = match markup {
+                Markup::Text(text) => split(&mut words, text, font, size, None, space),
+                Markup::Internal(title) => {
+                    let target = self.page_target(&self.renderer.site, &slug(title));
+                    split(&mut words, title, font, size, Some(target), space)
+                }
+                Markup::External { url, label } => split(
+                    &mut words,
+                    label,
+                    font,
+                    size,
+                    Some(Target::Uri(url.to_owned())),
+                    space,
+                ),
+            };
+        }
+        words
+    }
+
+    fn page_target(&self, site: &str, slug: &str) -> Target {
+        if site == self.renderer.site && self.renderer.included.contains(slug) {
+            Target::Page(slug.to_owned())
+        } else {
+            Target::Uri(format!("{}/{}.html", site, slug))
+        }
+    }
+
+    fn paragraph(&self, text: &str) -> Block {
+        Block::Text {
+            words: self.words(text, Font::Regular, BODY_SIZE),
+            indent: 0.0,
+            space_before: 4.0,
+        }
+    }
+
+    fn item(&mut self, item: &Item) -> Vec<Block> {
+        let text = item.text();
+        match item.r#type.as_str() {
+            "paragraph" => vec![self.paragraph(text)],
+            "markdown" => self.markdown(text),
+            "html" => html_text(text)
+                .lines()
+                .filter(|line| line.trim().len() > 0)
+                .map(|line| self.paragraph(line))
+                .collect(),
+            "image" => vec![self.image(item, text)],
+            "code" => vec![Block::Lines(text.lines().map(|l| l.to_owned()).collect())],
+            "reference" => self.reference(item, text),
+            "video" => self.video(item, text),
+            "pagefold" => vec![Block::Rule(text.to_owned())],
+            _ => self.unknown(&item.r#type, text),
+        }
+    }
+
+    /// Lays out the blocks Markdown parses into. Text is gathered until its style
+    /// changes, as the parser splits it around brackets, which the wiki's links use.
+    fn markdown(&self, text: &str) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let mut words = Vec::new();
+        let mut pending = String::new();
+        let mut style = Style::default();
+        let mut styles = Vec::new();
+        let mut code: Option<Vec<String>> = None;
+        let mut lists: Vec<Option<u64>> = Vec::new();
+        let mut quotes = 0;
+        for event in Parser::new(text) {
+            let level = lists.len() + quotes;
+            if let Some(lines) = code.as_mut() {
+                match event {
+                    Event::Text(text) => lines.extend(text.lines().map(|l| l.to_owned())),
+                    Event::End(TagEnd::CodeBlock) => {
+                        blocks.push(Block::Lines(code.take().unwrap()))
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            match event {
+                Event::Text(text) => pending.push_str(&text),
+                Event::Code(text) => {
+                    self.styled(&mut words, &mut pending, &style);
+                    let mut code = style.clone();
+                    code.font = Font::Mono;
+                    pending.push_str(&text);
+                    self.styled(&mut words, &mut pending, &code);
+                }
+                Event::Html(html) | Event::InlineHtml(html) => {
+                    pending.push_str(&html_text(&html).replace('\n', " "))
+                }
+                Event::SoftBreak | Event::HardBreak => pending.push(' '),
+                Event::Rule => blocks.push(Block::Rule(String::new())),
+                Event::Start(tag) => {
+                    self.styled(&mut words, &mut pending, &style);
+                    styles.push(style.clone());
+                    match tag {
+                        Tag::Heading { level, .. } => {
+                            style.font = Font::Bold;
+                            style.size = match level {
+                                HeadingLevel::H1 => 16.0,
+                                HeadingLevel::H2 => 14.0,
+                                _ => 12.0,
+                            };
+                        }
+                        Tag::Emphasis => style.font = Font::Italic,
+                        Tag::Strong => style.font = Font::Bold,
+                        Tag::Link { dest_url, .. } => {
+                            style.target = Some(Target::Uri(dest_url.into_string()))
+                        }
+                        Tag::CodeBlock(_) => {
+                            self.end_block(&mut blocks, &mut words, level);
+                            code = Some(Vec::new());
+                        }
+                        Tag::List(start) => {
+                            self.end_block(&mut blocks, &mut words, level);
+                            lists.push(start);
+                        }
+                        Tag::Item => {
+                            self.end_block(&mut blocks, &mut words, level);
+                            let marker = match lists.last_mut() {
+                                Some(Some(number)) => {
+                                    *number += 1;
+                                    format!("{}.", *number - 1)
+                                }
+                                _ => "\u{2022}".to_owned(),
+                            };
+                            split(&mut words, &marker, Font::Regular, BODY_SIZE, None, false);
+                            pending.push(' ');
+                        }
+                        Tag::BlockQuote(_) => {
+                            self.end_block(&mut blocks, &mut words, level);
+                            quotes += 1;
+                        }
+                        _ => {}
+                    }
+                }
+                Event::End(tag) => {
+                    self.styled(&mut words, &mut pending, &style);
+                    style = styles.pop().unwrap_or_default();
+                    match tag {
+                        TagEnd::Heading(_) => {
+                            self.end_block(&mut blocks, &mut words, level);
+                            if let Some(Block::Text { space_before, .. }) = blocks.last_mut() {
+                                *space_before = 8.0;
+                            }
+                        }
+                        TagEnd::Paragraph | TagEnd::Item => {
+                            self.end_block(&mut blocks, &mut words, level)
+                        }
+                        TagEnd::List(_) => {
+                            lists.pop();
+                        }
+                        TagEnd::BlockQuote(_) => quotes -= 1,
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.styled(&mut words, &mut pending, &style);
+        self.end_block(&mut blocks, &mut words, 0);
+        blocks
+    }
+
+    /// Adds the text gathered so far in the given style. Wiki links are resolved
+    /// unless the text is already the label of a Markdown link. Whitespace at the
+    /// end is kept, to separate the text from what follows in another style.
+    fn styled(&self, words: &mut Vec<Word>, pending: &mut String, style: &Style) {
+        match &style.target {
+            Some(target) => {
+                let target = Some(target.clone());
+                split(words, pending, style.font, style.size, target, false);
+            }
+            None => words.extend(self.words(pending, style.font, style.size)),
+        }
+        let space = pending.ends_with(char::is_whitespace);
+        pending.clear();
+        if space {
+            pending.push(' ');
+        }
+    }
+
+    /// Ends a block of the words so far, indented by the given number of levels.
+    fn end_block(&self, blocks: &mut Vec<Block>, words: &mut Vec<Word>, level: usize) {
+        if words.len() > 0 {
+            blocks.push(Block::Text {
+                words: words.drain(..).collect(),
+                indent: 14.0 * level as f32,
+                space_before: if level > 0 { 2.0 } else { 4.0 },
+            });
+        }
+    }
+
+    /// Embeds the image when it can be loaded and decoded, otherwise only its caption.
+    fn image(&mut self, item: &Item, caption: &str) -> Block {
+        let url = item.url.as_ref().map(|u| u.as_str()).unwrap_or("");
+        let caption_words = self.words(caption, Font::Italic, 9.0);
+        if let Some(&image) = self.image_urls.get(url) {
+            return Block::Image {
+                image,
+                caption: caption_words,
+            };
+        }
+        let image = match Asset::load(url, &self.renderer.site) {
+            Ok(asset) => Image::decode(&asset),
+            Err(error) => {
+                eprintln!("Unable to load image: {}", error);
+                None
+            }
+        };
+        match image {
+            Some(image) => {
+                self.images.push(image);
+                self.image_urls
+                    .insert(url.to_owned(), self.images.len() - 1);
+                Block::Image {
+                    image: self.images.len() - 1,
+                    caption: caption_words,
+                }
+            }
+            None => Block::Text {
+                words: self.words(&format!("[Image] {}", caption), Font::Italic, 9.0),
+                indent: 0.0,
+                space_before: 4.0,
+            },
+        }
+    }
+
+    fn reference(&self, item: &Item, synopsis: &str) -> Vec<Block> {
+        let site = item.reference_site(&self.renderer.site);
+        let name = item
+            .site
+            .as_ref()
+            .map(|s| s.as_str())
+            .unwrap_or_else(|| self.renderer.site_name());
+        let slug = item.slug.as_ref().map(|s| s.as_str()).unwrap_or("");
+        let title = item.title.as_ref().map(|t| t.as_str()).unwrap_or(slug);
+        let mut words = Vec::new();
+        split(
+            &mut words,
+            title,
+            Font::Bold,
+            BODY_SIZE,
+            Some(self.page_target(&site, slug)),
+            false,
+        );
+        words.extend(self.words(&format!(" \u{2014} {}", name), Font::Italic, BODY_SIZE));
+        vec![
+            Block::Text {
+                words,
+                indent: 0.0,
+                space_before: 6.0,
+            },
+            Block::Text {
+                words: self.words(synopsis, Font::Regular, BODY_SIZE),
+                indent: 14.0,
+                space_before: 0.0,
+            },
+        ]
+    }
+
+    fn video(&self, item: &Item, text: &str) -> Vec<Block> {
+        let video = match item.video() {
+            Some(video) => video,
+            None => return self.unknown("video", text),
+        };
+        let caption = video.caption.replace('\n', " ");
+        vec![self.paragraph(&format!("Video: [{} {}] {}", video.url, video.url, caption))]
+    }
+
+    fn unknown(&self, item_type: &str, text: &str) -> Vec<Block> {
+        vec![
+            Block::Text {
+                words: self.words(&format!("{} item", item_type), Font::Italic, 9.0),
+                indent: 0.0,
+                space_before: 4.0,
+            },
+            Block::Lines(text.lines().map(|l| l.to_owned()).collect()),
+        ]
+    }
+
+    /// Writes the document. Objects are numbered up front so that
+    /// links and bookmarks can refer to the sheets they jump to.
+    pub fn write(&self, out: &mut dyn Write) -> Result<(), Error> {
+        let first_image = 4 + FONTS.len();
+        let first_page = first_image + self.images.len();
+        let first_bookmark = first_page + 2 * self.pages.len();
+        let info = first_bookmark + self.outline.len();
+        let page_ref = |number: usize| first_page + 2 * number;
+        let destination = |slug: &str| {
+            self.anchors
+                .get(slug)
+                .map(|&(page, y)| format!("[{} 0 R /XYZ 0 {:.2} 0]", page_ref(page), y))
+        };
+
+        let mut objects: Vec<Vec<u8>> = Vec::new();
+        objects.push(
+            b"<< /Type /Catalog /Pages 2 0 R /Outlines 3 0 R /PageMode /UseOutlines >>".to_vec(),
+        );
+        let kids: Vec<String> = (0..self.pages.len())
+            .map(|number| format!("{} 0 R", page_ref(number)))
+            .collect();
+        objects.push(
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                kids.join(" "),
+                self.pages.len()
+            )
+            .into_bytes(),
+        );
+        objects.push(if self.outline.len() > 0 {
+            format!(
+                "<< /Type /Outlines /First {} 0 R /Last {} 0 R /Count {} >>",
+                first_bookmark,
+                info - 1,
+                self.outline.len()
+            )
+            .into_bytes()
+        } else {
+            b"<< /Type /Outlines /Count 0 >>".to_vec()
+        });
+        for font in &FONTS {
+            objects.push(
+                format!(
+                    "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+                    font.base_font()
+                )
+                .into_bytes(),
+            );
+        }
+        for image in &self.images {
+            let mut object = format!(
+                "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /{} \
+                 /BitsPerComponent 8 /Filter /{} {}/Length {} >>\nstream\n",
+                image.width,
+                image.height,
+                image.color_space,
+                image.filter,
+                image
+                    .parms
+                    .as_ref()
+                    .map(|p| format!("/DecodeParms {} ", p))
+                    .unwrap_or_default(),
+                image.data.len()
+            )
+            .into_bytes();
+            object.extend_from_slice(&image.data);
+            object.extend_from_slice(b"\nendstream");
+            objects.push(object);
+        }
+        let fonts: Vec<String> = FONTS
+            .iter()
+            .enumerate()
+            .map(|(i, font)| format!("/{} {} 0 R", font.resource(), 4 + i))
+            .collect();
+        let images: Vec<String> = (0..self.images.len())
+            .map(|i| format!("/Im{} {} 0 R", i + 1, first_image + i))
+            .collect();
+        for (number, page) in self.pages.iter().enumerate() {
+            let mut annotations = Vec::new();
+            for link in &page.links {
+                let action = match &link.target {
+                    Target::Page(slug) => match destination(slug) {
+                        Some(destination) => format!("/Dest {}", destination),
+                        None => continue,
+                    },
+                    Target::Uri(uri) => format!(
+                        "/A << /S /URI /URI {} >>",
+                        String::from_utf8_lossy(&literal(uri.as_bytes()))
+                    ),
+                };
+                annotations.push(format!(
+                    "<< /Type /Annot /Subtype /Link /Rect [{:.2} {:.2} {:.2} {:.2}] /Border [0 0 0] {} >>",
+                    link.rect[0], link.rect[1], link.rect[2], link.rect[3], action
+                ));
+            }
+            objects.push(
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Contents {} 0 R \
+                     /Resources << /Font << {} >> /XObject << {} >> >> /Annots [{}] >>",
+                    PAGE_WIDTH,
+                    PAGE_HEIGHT,
+                    page_ref(number) + 1,
+                    fonts.join(" "),
+                    images.join(" "),
+                    annotations.join(" ")
+                )
+                .into_bytes(),
+            );
+            let mut contents =
+                format!("<< /Length {} >>\nstream\n", page.content.len()).into_bytes();
+            contents.extend_from_slice(&page.content);
+            contents.extend_from_slice(b"\nendstream");
+            objects.push(contents);
+        }
+        for (number, (title, slug)) in self.outline.iter().enumerate() {
+            let mut bookmark = format!("<< /Title {} /Parent 3 0 R", unicode(title));
+            if number > 0 {
+                bookmark.push_str(&format!(" /Prev {} 0 R", first_bookmark + number - 1));
+            }
+            if number + 1 < self.outline.len() {
+                bookmark.push_str(&format!(" /Next {} 0 R", first_bookmark + number + 1));
+            }
+            if let Some(destination) = destination(slug) {
+                bookmark.push_str(&format!(" /Dest {}", destination));
+            }
+            bookmark.push_str(" >>");
+            objects.push(bookmark.into_bytes());
+        }
+        objects.push(
+            format!(
+                "<< /Title {} /Producer (wiki-rust) >>",
+                unicode(self.renderer.site_name())
+            )
+            .into_bytes(),
+        );
+
+        let mut pdf = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+        let mut offsets = Vec::new();
+        for (number, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n", number + 1).as_bytes());
+            pdf.extend_from_slice(object);
+            pdf.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = pdf.len();
+        pdf.extend_from_slice(
+            format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+        );
+        for offset in offsets {
+            pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        pdf.extend_from_slice(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+                objects.len() + 1,
+                info,
+                xref
+            )
+            .as_bytes(),
+        );
+        out.write_all(&pdf)?;
+        Ok(())
+    }
+}
+
+/// Appends the words of the text, all with the same style and target. `space` is
+/// whether whitespace came before the text, and the result whether it comes after.
+fn split(
+    words: &mut Vec<Word>,
+    text: &str,
+    font: Font,
+    size: f32,
+    target: Option<Target>,
+    space: bool,
+) -> bool {
+    let mut space = space || text.starts_with(char::is_whitespace);
+    for part in text.split_whitespace() {
+        words.push(Word {
+            text: win_ansi(part),
+            font,
+            size,
+            target: target.clone(),
+            space,
+        });
+        space = true;
+    }
+    if text.trim().len() > 0 {
+        text.ends_with(char::is_whitespace)
+    } else {
+        space
+    }
+}
+
+/// The text of an HTML fragment, with block elements on lines of their own.
+/// Ammonia keeps only those elements, without attributes, and escapes the text,
+/// so any `<` left in its output starts one of them.
+fn html_text(html: &str) -> String {
+    let cleaned = ammonia::Builder::empty()
+        .add_tags(BLOCK_TAGS)
+        .generic_attributes(HashSet::new())
+        .clean(html)
+        .to_string();
+    let decode = |text: &str| unescape(&text.replace("&nbsp;", " "));
+    let mut parts = cleaned.split('<');
+    let mut text = decode(parts.next().unwrap_or(""));
+    for part in parts {
+        text.push('\n');
+        text.push_str(&decode(part.splitn(2, '>').nth(1).unwrap_or("")));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spaced(words: &[Word]) -> Vec<(String, bool)> {
+        words
+            .iter()
+            .map(|word| (String::from_utf8_lossy(&word.text).into_owned(), word.space))
+            .collect()
+    }
+
+    #[test]
+    fn words_keep_the_spaces_around_links() {
+        let renderer = Renderer::new("http://fed.example");
+        let pdf = PdfDocument::new(&renderer);
+        let words = pdf.words(
+            "see [[Foo Bar]] now, Video: [http://x.example/v the video] or [[Baz]].",
+            Font::Regular,
+            BODY_SIZE,
+        );
+        let expected = vec![
+            ("see", false),
+            ("Foo", true),
+            ("Bar", true),
+            ("now,", true),
+            ("Video:", true),
+            ("the", true),
+            ("video", true),
+            ("or", true),
+            ("Baz", true),
+            (".", false),
+        ];
+        let expected: Vec<(String, bool)> = expected
+            .into_iter()
+            .map(|(text, space)| (text.to_owned(), space))
+            .collect();
+        assert_eq!(spaced(&words), expected);
+        assert!(words[1].target.is_some() && words[3].target.is_none());
+    }
+
+    /// The text of each block as it would be set, with its indent.
+    fn lines(blocks: &[Block]) -> Vec<(f32, String)> {
+        blocks
+            .iter()
+            .map(|block| match block {
+                Block::Text { words, indent, .. } => {
+                    let mut line = String::new();
+                    for word in words {
+                        if word.space && line.len() > 0 {
+                            line.push(' ');
+                        }
+                        line.extend(word.text.iter().map(|&byte| byte as char));
+                    }
+                    (*indent, line)
+                }
+                Block::Lines(lines) => (0.0, lines.join("\n")),
+                Block::Image { .. } => (0.0, "image".to_owned()),
+                Block::Rule(_) => (0.0, "rule".to_owned()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn markdown_is_laid_out_from_its_blocks() {
+        let renderer = Renderer::new("http://fed.example");
+        let pdf = PdfDocument::new(&renderer);
+        let blocks = pdf.markdown(
+            "# Title\n\nSome *very* [fine](https://x.example) `code`,\nsee [[Foo]].\n\n\
+             - one\n  - nested\n2. two\n\n```\nlet x = 1;\n```\n\n---\n",
+        );
+        let expected = vec![
+            (0.0, "Title"),
+            (0.0, "Some very fine code, see Foo."),
+            (14.0, "\u{95} one"),
+            (28.0, "\u{95} nested"),
+            (14.0, "2. two"),
+            (0.0, "let x = 1;"),
+            (0.0, "rule"),
+        ];
+        let expected: Vec<(f32, String)> = expected
+            .into_iter()
+            .map(|(indent, text)| (indent, text.to_owned()))
+            .collect();
+        assert_eq!(lines(&blocks), expected);
+        let words = match &blocks[1] {
+            Block::Text { words, .. } => words,
+            _ => panic!("not text"),
+        };
+        assert!(words[1].font == Font::Italic);
+        assert!(words[2].target.is_some() && words[3].font == Font::Mono);
+        assert!(words[6].target.is_some() && words[5].target.is_none());
+    }
+
+    #[test]
+    fn html_keeps_block_elements_on_lines_of_their_own() {
+        assert_eq!(
+            html_text(
+                "<div class=\"x\"><p title=\"a>b\">One &amp; <b>two</b></p>\
+                 <script>no()</script>three<br>four&nbsp;five</div>"
+            ),
+            "\n\nOne & two\nthree\nfour five\n"
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use crate::format::escape_xml as escape;
+use crate::journal::PageHistory;
+use crate::links::{internal_links, parse_links, slug, Markup};
+use crate::{Entry, Item, Page};
+use chrono::NaiveDateTime;
+use pulldown_cmark::{html, Parser};
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+pub const THEMES: &[&str] = &["default", "serif", "compact"];
+
+/// Sanitizes html from the page, keeping the classes links are styled by.
+fn clean(html: &str) -> String {
+    ammonia::Builder::default()
+        .add_allowed_classes("a", &["internal", "external"])
+        .add_allowed_classes("span", &["internal"])
+        .clean(html)
+        .to_string()
+}
+
+fn theme_rules(theme: &str) -> Option<&'static str> {
+    match theme {
+        "default" => Some(""),
+        "serif" => Some(include_str!("themes/serif.css")),
+        "compact" => Some(include_str!("themes/compact.css")),
+        _ => None,
+    }
+}
+
+/// The built-in print stylesheet followed by the rules of the named theme.
+pub fn stylesheet(theme: &str) -> Option<String> {
+    Some(format!(
+        "{}\n{}\n{}",
+        include_str!("themes/base.css"),
+        include_str!("themes/print.css"),
+        theme_rules(theme)?
+    ))
+}
+
+/// The stylesheet for reading in a browser, without the print stylesheet's page breaks.
+pub fn screen_stylesheet(theme: &str) -> Option<String> {
+    Some(format!(
+        "{}\n{}\n{}",
+        include_str!("themes/base.css"),
+        include_str!("themes/screen.css"),
+        theme_rules(theme)?
+    ))
+}
+
+/// The start of an HTML document, up to and including the opening body tag.
+pub fn document_start(title: &str, css: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>\n{}\n</style>\n</head>\n<body>\n",
+        escape(title),
+        css
+    )
+}
+
+pub const DOCUMENT_END: &str = "</body>\n</html>\n";
+
+/// How links to pages outside the document are rendered.
+#[derive(Clone, Copy, PartialEq)]
+pub enum LinkStyle {
+    /// A link to the page on its site.
+    Site,
+    /// Plain text with a numbered note giving the page's url.
+    Footnotes,
+}
+
+/// Renders pages of a site, and their story items, as HTML.
+/// Links to pages included in the document point to their anchors.
+pub struct Renderer {
+    pub site: String,
+    pub included: HashSet<String>,
+    pub link_style: LinkStyle,
+    /// When set, each page is a file of its own, named for its slug with this
+    /// extension, and links to included pages point to that file.
+    pub file_extension: Option<String>,
+    notes: RefCell<Vec<String>>,
+}
+
+impl Renderer {
+    pub fn new(site: &str) -> Renderer {
+        Renderer {
+            site: site.trim_end_matches('/').to_owned(),
+            included: HashSet::new(),
+            link_style: LinkStyle::Site,
+            file_extension: None,
+            notes: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The host the site is known by, as used in references.
+    pub fn site_name(&self) -> &str {
+        self.site.splitn(2, "://").last().unwrap()
+    }
+
+    /// Where a link to an included page points.
+    pub fn href(&self, slug: &str) -> String {
+        match &self.file_extension {
+            Some(extension) => format!("{}.{}#{}", slug, extension, slug),
+            None => format!("#{}", slug),
+        }
+    }
+
+    pub fn page(&self, slug: &str, page: &Page) -> String {
+        let mut html = format!(
+            "<div class=\"page\" id=\"{}\">\n<h1>{}</h1>\n<div class=\"story\">\n",
+            escape(slug),
+            escape(&page.title)
+        );
+        for item in &page.story {
+            html.push_str(&self.item(item));
+            html.push('\n');
+        }
+        html.push_str("</div>\n");
+        let notes: Vec<String> = self.notes.borrow_mut().drain(..).collect();
+        if notes.len() > 0 {
+            html.push_str("<ol class=\"footnotes\">\n");
+            for (number, url) in notes.iter().enumerate() {
+                html.push_str(&format!(
+                    "<li id=\"{}-note-{}\">{}</li>\n",
+                    escape(slug),
+                    number + 1,
+                    escape(url)
+                ));
+            }
+            html.push_str("</ol>\n");
+        }
+        html.push_str("</div>\n");
+        html
+    }
+
+    /// A title page naming the site. Front matter ids start with an underscore,
+    /// which slugs never contain.
+    pub fn cover(&self, date: &NaiveDateTime) -> String {
+        format!(
+            "<div class=\"cover\" id=\"_cover\">\n<img class=\"favicon\" src=\"{}/favicon.png\" alt=\"\">\n\
+             <h1>{}</h1>\n<p class=\"date\">{}</p>\n</div>\n",
+            escape(&self.site),
+            escape(self.site_name()),
+            date.format("%B %e, %Y")
+        )
+    }
+
+    pub fn contents(&self, pages: &[(Entry, Page)]) -> String {
+        let mut html =
+            String::from("<div class=\"contents\" id=\"_contents\">\n<h1>Contents</h1>\n<ol>\n");
+        for (entry, page) in pages {
+            html.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                escape(&self.href(&entry.slug)),
+                escape(&page.title)
+            ));
+        }
+        html.push_str("</ol>\n</div>\n");
+        html
+    }
+
+    /// An index of page titles and link text, each listing the pages it occurs on.
+    pub fn index(&self, pages: &[(Entry, Page)]) -> String {
+        let mut terms: BTreeMap<String, (String, BTreeSet<usize>)> = BTreeMap::new();
+        let mut add = |term: &str, page: usize| {
+            terms
+                .entry(term.to_lowercase())
+                .or_insert_with(|| (term.to_owned(), BTreeSet::new()))
+                .1
+                .insert(page);
+        };
+        for (number, (_, page)) in pages.iter().enumerate() {
+            add(&page.title, number);
+            for item in &page.story {
+                for title in internal_links(item.text()) {
+                    add(title, number);
+                }
+            }
+        }
+        let mut html = String::from("<div class=\"index\" id=\"_index\">\n<h1>Index</h1>\n");
+        let mut letter = None;
+        for (key, (term, occurrences)) in &terms {
+            let first = key.chars().next().unwrap_or(' ').to_ascii_uppercase();
+            if letter != Some(first) {
+                if letter.is_some() {
+                    html.push_str("</ul>\n");
+                }
+                html.push_str(&format!("<h2>{}</h2>\n<ul>\n", escape(&first.to_string())));
+                letter = Some(first);
+            }
+            let references: Vec<String> = occurrences
+                .iter()
+                .map(|&number| {
+                    let (entry, page) = &pages[number];
+                    format!(
+                        "<a href=\"{}\">{}</a>",
+                        escape(&self.href(&entry.slug)),
+                        escape(&page.title)
+                    )
+                })
+                .collect();
+            html.push_str(&format!(
+                "<li>{} — {}</li>\n",
+                escape(term),
+                references.join(", ")
+            ));
+        }
+        if letter.is_some() {
+            html.push_str("</ul>\n");
+        }
+        html.push_str("</div>\n");
+        html
+    }
+
+    /// An appendix giving the provenance of each page.
+    pub fn history(&self, pages: &[(Entry, Page)]) -> String {
+        let date = |date: Option<NaiveDateTime>| {
+            date.map(|d| d.format("%Y-%m-%d").to_string())
+                .unwrap_or_else(|| "unknown".to_owned())
+        };
+        let mut html = String::from(
+            "<div class=\"history\" id=\"_history\">\n<h1>History</h1>\n<table>\n\
+             <tr><th>Page</th><th>Created</th><th>Edits</th><th>Forked from</th><th>Last change</th></tr>\n",
+        );
+        for (entry, page) in pages {
+            let history = PageHistory::from_page(page);
+            html.push_str(&format!(
+                "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape(&self.href(&entry.slug)),
+                escape(&page.title),
+                date(history.created),
+                history.edits,
+                escape(&history.forked_from.join(", ")),
+                date(history.last_change)
+            ));
+        }
+        html.push_str("</table>\n</div>\n");
+        html
+    }
+
+    pub fn item(&self, item: &Item) -> String {
+        let text = item.text();
+        let body = match item.r#type.as_str() {
+            "paragraph" => format!("<p>{}</p>", clean(&self.resolve_links(text, false))),
+            "markdown" => self.markdown(text),
+            "html" => clean(&self.resolve_links(text, false)),
+            "image" => self.image(item, text),
+            "code" => format!("<pre>{}</pre>", escape(text)),
+            "reference" => self.reference(item, text),
+            "video" => self.video(item, text),
+            "pagefold" => format!("<hr><span>{}</span>", escape(text)),
+            _ => self.unknown(&item.r#type, text),
+        };
+        format!(
+            "<div class=\"item {}\">{}</div>",
+            escape(&item.r#type),
+            body
+        )
+    }
+
+    /// Replaces the wiki's link markup with anchors.
+    /// Text between links is escaped unless it is already HTML.
+    pub fn resolve_links(&self, text: &str, escape_text: bool) -> String {
+        let mut html = String::new();
+        for markup in parse_links(text) {
+            match markup {
+                Markup::Text(text) if escape_text => html.push_str(&escape(text)),
+                Markup::Text(text) => html.push_str(text),
+                Markup::Internal(title) => html.push_str(&self.internal_link(title)),
+                Markup::External { url, label } => html.push_str(&format!(
+                    "<a class=\"external\" href=\"{}\">{}</a>",
+                    escape(url),
+                    escape(label)
+                )),
+            }
+        }
+        html
+    }
+
+    pub fn internal_link(&self, title: &str) -> String {
+        self.page_link(&self.site, &slug(title), &escape(title))
+    }
+
+    /// A link to a page of the given site, whose label is already HTML.
+    fn page_link(&self, site: &str, slug: &str, label: &str) -> String {
+        if site == self.site && self.included.contains(slug) {
+            return format!(
+                "<a class=\"internal\" href=\"{}\">{}</a>",
+                escape(&self.href(slug)),
+                label
+            );
+        }
+        let url = format!("{}/{}.html", site, slug);
+        match self.link_style {
+            LinkStyle::Site => format!(
+                "<a class=\"internal\" href=\"{}\">{}</a>",
+                escape(&url),
+                label
+            ),
+            LinkStyle::Footnotes => {
+                let mut notes = self.notes.borrow_mut();
+                notes.push(url);
+                format!(
+                    "<span class=\"internal\">{}</span><sup>{}</sup>",
+                    label,
+                    notes.len()
+                )
+            }
+        }
+    }
+
+    /// Links to other sites are marked external, as resolve_links does for paragraphs.
+    fn markdown(&self, text: &str) -> String {
+        let mut html = String::new();
+        html::push_html(&mut html, Parser::new(text));
+        let external = Regex::new(r#"<a href="(https?://[^"]*)""#).unwrap();
+        let html = external.replace_all(&html, r#"<a class="external" href="$1""#);
+        clean(&self.resolve_links(&html, false))
+    }
+
+    fn image(&self, item: &Item, caption: &str) -> String {
+        let url = item.url.as_ref().map(|u| u.as_str()).unwrap_or("");
+        format!(
+            "<figure><img src=\"{}\" alt=\"{}\"><figcaption>{}</figcaption></figure>",
+            escape(url),
+            escape(caption),
+            self.resolve_links(caption, true)
+        )
+    }
+
+    /// A card naming the target site, which is this site when not given.
+    fn reference(&self, item: &Item, synopsis: &str) -> String {
+        let site = item.reference_site(&self.site);
+        let name = item
+            .site
+            .as_ref()
+            .map(|s| s.as_str())
+            .unwrap_or_else(|| self.site_name());
+        let slug = item.slug.as_ref().map(|s| s.as_str()).unwrap_or("");
+        let title = item.title.as_ref().map(|t| t.as_str()).unwrap_or(slug);
+        format!(
+            "<div class=\"card\"><img class=\"favicon\" src=\"{site}/favicon.png\" alt=\"\">\
+             {link} <span class=\"site\">{name}</span><p>{synopsis}</p></div>",
+            site = escape(&site),
+            link = self.page_link(&site, slug, &escape(title)),
+            name = escape(name),
+            synopsis = self.resolve_links(synopsis, true)
+        )
+    }
+
+    fn video(&self, item: &Item, text: &str) -> String {
+        let video = match item.video() {
+            Some(video) => video,
+            None => return self.unknown("video", text),
+        };
+        let preview = match &video.thumbnail {
+            Some(thumbnail) => format!("<img src=\"{}\" alt=\"Video\">", escape(&thumbnail)),
+            None => "Video".to_owned(),
+        };
+        format!(
+            "<figure><a href=\"{url}\">{preview}</a><figcaption>{caption} \
+             <a href=\"{url}\">{url}</a></figcaption></figure>",
+            url = escape(&video.url),
+            preview = preview,
+            caption = self.resolve_links(&video.caption, true)
+        )
+    }
+
+    fn unknown(&self, item_type: &str, text: &str) -> String {
+        format!(
+            "<div class=\"unknown\"><p class=\"type\">{} item</p><pre>{}</pre></div>",
+            escape(item_type),
+            escape(text)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(item_type: &str, text: &str) -> String {
+        let item: Item =
+            serde_json::from_value(serde_json::json!({"type": item_type, "id": "1", "text": text}))
+                .unwrap();
+        Renderer::new("http://fed.example").item(&item)
+    }
+
+    #[test]
+    fn markdown_links_keep_their_classes() {
+        let html = render("markdown", "[[Foo]] and [bar](https://bar.example)");
+        assert!(html.contains("<a class=\"internal\" href=\"http://fed.example/foo.html\""));
+        assert!(html.contains("<a class=\"external\" href=\"https://bar.example\""));
+    }
+
+    #[test]
+    fn html_links_keep_their_classes() {
+        let html = render(
+            "html",
+            "<p>[[Foo]] [http://bar.example bar]</p><script>x</script>",
+        );
+        assert!(html.contains("<a class=\"internal\""));
+        assert!(html.contains("<a class=\"external\" href=\"http://bar.example\""));
+        assert!(!html.contains("script"));
+    }
+
+    #[test]
+    fn paragraphs_keep_their_html() {
+        let html = render(
+            "paragraph",
+            "<b>Bold</b> [[Foo]] & <i onclick=\"x()\">more</i><script>x</script>",
+        );
+        assert!(html.contains("<p><b>Bold</b> <a class=\"internal\""));
+        assert!(html.contains("&amp; <i>more</i>"));
+        assert!(!html.contains("script") && !html.contains("onclick"));
+    }
+}
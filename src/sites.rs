@@ -0,0 +1,116 @@
+use crate::format::unescape_xml as unescape;
+use url::Url;
+
+/// Sites listed one per line. Blank lines and `#` comments are ignored.
+pub fn read_site_list(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter(|line| line.len() > 0)
+        .map(|line| line.to_owned())
+        .collect()
+}
+
+/// Sites referenced by the outline elements of an OPML document.
+/// The htmlUrl or xmlUrl attribute is used, falling back to the text.
+pub fn read_opml(text: &str) -> Vec<String> {
+    let mut sites = Vec::new();
+    for outline in text.split("<outline").skip(1) {
+        let tag = &outline[..outline.find('>').unwrap_or(outline.len())];
+        let site = ["htmlUrl", "xmlUrl", "url"]
+            .iter()
+            .filter_map(|name| attribute(tag, name))
+            .filter_map(|url| Url::parse(&url).ok())
+            .filter_map(|url| site_of(&url))
+            .next()
+            .or_else(|| attribute(tag, "text").filter(|t| is_host(t)));
+        if let Some(site) = site {
+            sites.push(site);
+        }
+    }
+    sites
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    while let Some(index) = rest.find(name) {
+        let before = rest[..index].chars().last();
+        let after = rest[index + name.len()..].trim_start();
+        rest = &rest[index + name.len()..];
+        if before.map(|c| !c.is_whitespace()).unwrap_or(true) || !after.starts_with('=') {
+            continue;
+        }
+        let value = after[1..].trim_start();
+        let quote = value.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        let end = value[1..].find(quote)?;
+        return Some(unescape(&value[1..1 + end]));
+    }
+    None
+}
+
+/// The scheme, host and port of the url, so that https sites stay https.
+fn site_of(url: &Url) -> Option<String> {
+    let origin = url.origin();
+    if origin.is_tuple() {
+        Some(origin.ascii_serialization())
+    } else {
+        None
+    }
+}
+
+fn is_host(text: &str) -> bool {
+    text.contains('.') && !text.contains(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn site_list_skips_comments_and_blank_lines() {
+        let text = "# neighbors\nfed.example\n\n  other.example:3000  # at home\n#old.example\n";
+        assert_eq!(
+            read_site_list(text),
+            vec!["fed.example", "other.example:3000"]
+        );
+    }
+
+    #[test]
+    fn opml_keeps_the_origin_of_urls() {
+        let opml = r#"<?xml version="1.0"?>
+<opml version="2.0"><body>
+<outline text="Fed" htmlUrl="https://fed.example/view/welcome-visitors"/>
+<outline text="Feed" type="rss" xmlUrl="http://other.example:3000/system/feed.rss"/>
+<outline text='Quoted' url='https://quoted.example/'>
+</body></opml>"#;
+        assert_eq!(
+            read_opml(opml),
+            vec![
+                "https://fed.example",
+                "http://other.example:3000",
+                "https://quoted.example"
+            ]
+        );
+    }
+
+    #[test]
+    fn opml_falls_back_to_host_names() {
+        let opml = r#"<outline text="A &amp; B" htmlUrl="https://fed.example/?a=1&amp;b=2"/>
+<outline text="bare.example"/>
+<outline text="Not a site"/>"#;
+        assert_eq!(read_opml(opml), vec!["https://fed.example", "bare.example"]);
+    }
+
+    #[test]
+    fn opml_attributes_are_whole_names_and_unescaped() {
+        let opml =
+            r#"<outline myhtmlUrl="https://wrong.example" htmlUrl="https://right.example"/>"#;
+        assert_eq!(read_opml(opml), vec!["https://right.example"]);
+        assert_eq!(
+            attribute(r#" title="x &lt;y&gt; &quot;z&quot;""#, "title").unwrap(),
+            "x <y> \"z\""
+        );
+    }
+}
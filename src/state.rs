@@ -0,0 +1,188 @@
+use crate::RecentChange;
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{create_dir_all, File};
+use std::path::{Path, PathBuf};
+
+/// The date up to which every change of each site has been reported,
+/// changes reported since then, and the changes marked as read while browsing.
+/// Dates are kept as milliseconds, the same as in sitemaps.
+#[derive(Deserialize, Serialize, Default)]
+pub struct SeenState {
+    #[serde(default)]
+    pub sites: HashMap<String, i64>,
+    /// Changes newer than their site's date that were reported while older ones were
+    /// left out by --limit, by their read_key.
+    #[serde(default)]
+    pub seen: HashSet<String>,
+    #[serde(default)]
+    pub read: HashSet<String>,
+}
+
+impl SeenState {
+    pub fn default_path() -> PathBuf {
+        dirs::data_dir()
+            .expect("Unable to find data dir.")
+            .join("wiki-rust")
+            .join("seen.json")
+    }
+
+    /// Loads the state file, starting empty if it does not exist yet.
+    pub fn load(path: &Path) -> Result<SeenState, Error> {
+        if !path.exists() {
+            return Ok(SeenState::default());
+        }
+        let reader = File::open(path)?;
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    /// A newer change to the same page is a different key, so it shows as unread.
+    pub fn read_key(change: &RecentChange) -> String {
+        format!(
+            "{}/{}@{}",
+            change.site.name,
+            change.entry.slug,
+            change.entry.date.timestamp_millis()
+        )
+    }
+
+    pub fn is_read(&self, change: &RecentChange) -> bool {
+        self.read.contains(&SeenState::read_key(change))
+    }
+
+    /// Marks the change as read, or unread if it already was.
+    pub fn toggle_read(&mut self, change: &RecentChange) {
+        let key = SeenState::read_key(change);
+        if !self.read.remove(&key) {
+            self.read.insert(key);
+        }
+    }
+
+    pub fn is_unseen(&self, change: &RecentChange) -> bool {
+        let date = change.entry.date.timestamp_millis();
+        match self.sites.get(&change.site.name) {
+            Some(seen) if date <= *seen => false,
+            _ => !self.seen.contains(&SeenState::read_key(change)),
+        }
+    }
+
+    /// Records the shown changes as seen, along with anything older on their sites.
+    /// Changes left out by --limit stay unseen: on those sites the shown changes,
+    /// which are newer, are remembered one by one instead.
+    pub fn mark_seen(&mut self, shown: &[RecentChange], left_out: &[RecentChange]) {
+        let limited: HashSet<&str> = left_out.iter().map(|c| c.site.name.as_str()).collect();
+        let mut complete: HashSet<&str> = HashSet::new();
+        for change in shown {
+            if limited.contains(change.site.name.as_str()) {
+                self.seen.insert(SeenState::read_key(change));
+            } else {
+                complete.insert(&change.site.name);
+                self.advance(&change.site.name, change.entry.date.timestamp_millis());
+            }
+        }
+        // Once nothing on a site is left out, its date covers the changes remembered one by one.
+        let remembered: Vec<(String, i64)> = self.seen.iter().filter_map(|k| key_date(k)).collect();
+        for (site, date) in remembered {
+            if complete.contains(site.as_str()) {
+                self.advance(&site, date);
+            }
+        }
+        let sites = &self.sites;
+        self.seen.retain(|key| match key_date(key) {
+            Some((site, date)) => sites.get(&site).map(|seen| date > *seen).unwrap_or(true),
+            None => false,
+        });
+    }
+
+    fn advance(&mut self, site: &str, date: i64) {
+        let seen = self.sites.entry(site.to_owned()).or_insert(0);
+        *seen = (*seen).max(date);
+    }
+}
+
+/// The site and date of a key made by read_key.
+fn key_date(key: &str) -> Option<(String, i64)> {
+    let slash = key.find('/')?;
+    let at = key.rfind('@')?;
+    Some((key[..slash].to_owned(), key[at + 1..].parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Entry, Sitemap};
+
+    fn sitemap(dates: &[u64]) -> Sitemap {
+        let entries = dates
+            .iter()
+            .map(|date| {
+                serde_json::from_value::<Entry>(serde_json::json!({
+                    "slug": format!("page-{}", date),
+                    "title": format!("Page {}", date),
+                    "date": date,
+                    "synopsis": "",
+                }))
+                .unwrap()
+            })
+            .collect();
+        Sitemap {
+            name: "fed.example".to_owned(),
+            url: "http://fed.example".to_owned(),
+            entries,
+        }
+    }
+
+    fn unseen(state: &SeenState, site: &Sitemap) -> Vec<String> {
+        site.entries
+            .iter()
+            .map(|entry| RecentChange { site, entry })
+            .filter(|change| state.is_unseen(change))
+            .map(|change| change.entry.slug.clone())
+            .collect()
+    }
+
+    #[test]
+    fn changes_left_out_by_limit_stay_unseen() {
+        let site = sitemap(&[5000, 4000, 3000, 2000, 1000]);
+        let changes: Vec<RecentChange> = site
+            .entries
+            .iter()
+            .map(|entry| RecentChange { site: &site, entry })
+            .collect();
+        let mut state = SeenState::default();
+        state.mark_seen(&changes[..2], &changes[2..]);
+        assert_eq!(
+            unseen(&state, &site),
+            vec!["page-3000", "page-2000", "page-1000"]
+        );
+        state.mark_seen(&changes[2..4], &changes[4..]);
+        assert_eq!(unseen(&state, &site), vec!["page-1000"]);
+        state.mark_seen(&changes[4..], &[]);
+        assert_eq!(unseen(&state, &site), Vec::<String>::new());
+        assert_eq!(state.sites["fed.example"], 5000);
+        assert!(state.seen.is_empty());
+    }
+
+    #[test]
+    fn shown_changes_mark_older_ones_seen() {
+        let site = sitemap(&[3000, 2000, 1000]);
+        let changes: Vec<RecentChange> = site
+            .entries
+            .iter()
+            .map(|entry| RecentChange { site: &site, entry })
+            .collect();
+        let mut state = SeenState::default();
+        state.mark_seen(&changes[1..2], &[]);
+        assert_eq!(unseen(&state, &site), vec!["page-3000"]);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::Neighborhood;
+use chrono::{Datelike, Duration, NaiveDateTime, Utc};
+use failure::Error;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Serialize)]
+pub struct SiteStats {
+    pub site: String,
+    pub pages: usize,
+    pub last_edit: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PageCount {
+    /// The site the page is on.
+    pub site: String,
+    pub slug: String,
+    pub title: String,
+    pub count: usize,
+}
+
+/// A page forked into sites of the neighborhood, by slug.
+#[derive(Serialize)]
+pub struct ForkCount {
+    pub slug: String,
+    pub title: String,
+    /// The number of sites holding a fork of the page.
+    pub count: usize,
+    pub sites: Vec<String>,
+    /// The sites the forks were taken from.
+    pub sources: Vec<String>,
+}
+
+/// Activity statistics for a neighborhood.
+/// Journal based numbers only cover pages changed within the window.
+#[derive(Serialize)]
+pub struct Stats {
+    pub window_days: i64,
+    pub sites: Vec<SiteStats>,
+    pub edits_per_day: BTreeMap<String, usize>,
+    pub edits_per_week: BTreeMap<String, usize>,
+    pub most_edited: Vec<PageCount>,
+    pub most_forked: Vec<ForkCount>,
+    /// Items on the pages changed within the window.
+    pub item_types_in_window: BTreeMap<String, usize>,
+    pub dormant_days: i64,
+    pub dormant: Vec<String>,
+}
+
+impl Stats {
+    pub fn from_neighborhood(
+        neighborhood: &Neighborhood,
+        window_days: i64,
+        dormant_days: i64,
+        top: usize,
+    ) -> Result<Stats, Error> {
+        let now = Utc::now().naive_utc();
+        let window_start = now - Duration::days(window_days);
+        let dormant_start = now - Duration::days(dormant_days);
+        let mut stats = Stats {
+            window_days,
+            sites: Vec::new(),
+            edits_per_day: BTreeMap::new(),
+            edits_per_week: BTreeMap::new(),
+            most_edited: Vec::new(),
+            most_forked: Vec::new(),
+            item_types_in_window: BTreeMap::new(),
+            dormant_days,
+            dormant: Vec::new(),
+        };
+        let mut forks: HashMap<String, ForkCount> = HashMap::new();
+        for site in &neighborhood.sites {
+            let last_edit = site.entries.iter().map(|e| e.date).max();
+            stats.sites.push(SiteStats {
+                site: site.name.clone(),
+                pages: site.entries.len(),
+                last_edit: last_edit.map(|d| d.format("%Y-%m-%d").to_string()),
+            });
+            if last_edit.map(|d| d < dormant_start).unwrap_or(true) {
+                stats.dormant.push(site.name.clone());
+            }
+            for entry in site.entries.iter().filter(|e| e.date >= window_start) {
+                let page = match site.page(&entry.slug) {
+                    Ok(page) => page,
+                    Err(err) => {
+                        eprintln!("Skipping {}/{}: {}", site.name, entry.slug, err);
+                        continue;
+                    }
+                };
+                for item in &page.story {
+                    *stats
+                        .item_types_in_window
+                        .entry(item.r#type.clone())
+                        .or_insert(0) += 1;
+                }
+                let mut edits = 0;
+                let mut forked = false;
+                let mut sources = Vec::new();
+                for change in page.journal.iter().filter(|c| c.date >= window_start) {
+                    edits += 1;
+                    *stats.edits_per_day.entry(day(&change.date)).or_insert(0) += 1;
+                    *stats.edits_per_week.entry(week(&change.date)).or_insert(0) += 1;
+                    if change.r#type == "fork" {
+                        forked = true;
+                        sources.extend(change.site.clone());
+                    }
+                }
+                if forked {
+                    let fork = forks
+                        .entry(entry.slug.clone())
+                        .or_insert_with(|| ForkCount {
+                            slug: entry.slug.clone(),
+                            title: entry.title.clone(),
+                            count: 0,
+                            sites: Vec::new(),
+                            sources: Vec::new(),
+                        });
+                    if !fork.sites.contains(&site.name) {
+                        fork.sites.push(site.name.clone());
+                        fork.count += 1;
+                    }
+                    for source in sources {
+                        if !fork.sources.contains(&source) {
+                            fork.sources.push(source);
+                        }
+                    }
+                }
+                if edits > 0 {
+                    stats.most_edited.push(PageCount {
+                        site: site.name.clone(),
+                        slug: entry.slug.clone(),
+                        title: entry.title.clone(),
+                        count: edits,
+                    });
+                }
+            }
+        }
+        stats.most_edited.sort_by(|a, b| b.count.cmp(&a.count));
+        stats.most_edited.truncate(top);
+        stats.most_forked = forks.into_iter().map(|(_, v)| v).collect();
+        stats
+            .most_forked
+            .sort_by(|a, b| b.count.cmp(&a.count).then(a.slug.cmp(&b.slug)));
+        stats.most_forked.truncate(top);
+        Ok(stats)
+    }
+}
+
+fn day(date: &NaiveDateTime) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+fn week(date: &NaiveDateTime) -> String {
+    let week = date.iso_week();
+    format!("{}-W{:02}", week.year(), week.week())
+}